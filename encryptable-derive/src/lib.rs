@@ -0,0 +1,327 @@
+//! Derive macro for the [encryptable](https://docs.rs/encryptable) crate, use it through the
+//! `derive` feature of `encryptable` instead of depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, parse_quote, punctuated::Punctuated, Data, DeriveInput, Fields, Ident,
+    LitStr, Path, Token,
+};
+
+/// Implements `Encryptable<Self>` for a serde type, and adds `encrypt(&self, password)` and
+/// `decrypt(&Encrypted<Self>, password)` methods to it.
+///
+/// The type is serialized with serde before it is encrypted, so it has to implement
+/// `Serialize` and `DeserializeOwned`.
+///
+/// # Attributes
+/// `#[encryptable(...)]` on the type accepts:
+/// - `format = Cbor`: the serde format, any type implementing `encryptable::Format`. A single
+///   identifier refers to one of the formats in `encryptable`, defaults to `Json`
+/// - `cipher = Aes256Gcm`: a variant of `encryptable::Cipher`
+/// - `config = path::to::function`: a `fn() -> encryptable::Config` giving the full config, for
+///   example to change the kdf. `cipher` is applied on top of it
+/// - `tag = "user-v1"`: the `Encryptable::type_tag`, stored in the encrypted data and checked
+///   before decrypting
+#[proc_macro_derive(Encryptable, attributes(encryptable))]
+pub fn derive_encryptable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Adds a companion "sealed" struct where the fields marked `#[encrypt]` are `Encrypted<T>` and
+/// the other fields are copied as they are. For `struct User` this generates `struct UserSealed`
+/// along with:
+/// - `User::seal(&self, &DerivedKey) -> Result<UserSealed, EncryptError>`
+/// - `User::seal_with_password(&self, password) -> Result<UserSealed, EncryptError>`
+/// - `UserSealed::unseal(&self, &DerivedKey) -> Result<User, DecryptError>`
+/// - `UserSealed::unseal_with_password(&self, password) -> Result<User, DecryptError>`
+///
+/// All fields of a record share one key, so the password variants only run the kdf once. The
+/// encrypted fields are serialized with serde, and the other fields have to implement `Clone`.
+///
+/// Each field is bound to the struct and field name as associated data, so encrypted values can
+/// not be swapped between fields. `seal_with_aad` and `unseal_with_aad` also take associated data
+/// of the caller, such as the primary key of the row, so values can not be moved between records
+/// either:
+/// - `User::seal_with_aad(&self, &DerivedKey, aad: &[u8]) -> Result<UserSealed, EncryptError>`
+/// - `UserSealed::unseal_with_aad(&self, &DerivedKey, aad: &[u8]) -> Result<User, DecryptError>`
+///
+/// Fernet can not authenticate associated data, so it is not supported.
+///
+/// # Attributes
+/// `#[encryptable(...)]` on the struct accepts `format`, `cipher` and `config` like
+/// `#[derive(Encryptable)]`, as well as:
+/// - `sealed = Name`: the name of the sealed struct, defaults to the struct name followed by
+///   `Sealed`
+/// - `sealed_derive(Debug, Clone, ...)`: derives to add to the sealed struct
+#[proc_macro_derive(EncryptFields, attributes(encryptable, encrypt))]
+pub fn derive_encrypt_fields(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_fields(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Options from `#[encryptable(...)]`
+#[derive(Default)]
+struct Options {
+    format: Option<Path>,
+    cipher: Option<Ident>,
+    config: Option<Path>,
+    tag: Option<LitStr>,
+    sealed: Option<Ident>,
+    sealed_derive: Vec<Path>,
+}
+
+impl Options {
+    fn parse(input: &DeriveInput) -> syn::Result<Self> {
+        let mut options = Self::default();
+        for attr in input
+            .attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("encryptable"))
+        {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("format") {
+                    options.format = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("cipher") {
+                    options.cipher = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("config") {
+                    options.config = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("tag") {
+                    options.tag = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("sealed") {
+                    options.sealed = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("sealed_derive") {
+                    let content;
+                    syn::parenthesized!(content in meta.input);
+                    options
+                        .sealed_derive
+                        .extend(Punctuated::<Path, Token![,]>::parse_terminated(&content)?);
+                } else {
+                    return Err(meta.error("unknown encryptable attribute"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(options)
+    }
+
+    /// The format type
+    fn format(&self) -> TokenStream2 {
+        match &self.format {
+            Some(path) if path.get_ident().is_some() => quote!(::encryptable::#path),
+            Some(path) => quote!(#path),
+            None => quote!(::encryptable::Json),
+        }
+    }
+
+    /// Expression building the `Config`
+    fn config(&self) -> TokenStream2 {
+        let mut config = match &self.config {
+            Some(path) => quote!(#path()),
+            None => quote!(::encryptable::Config::new()),
+        };
+        if let Some(cipher) = &self.cipher {
+            config = quote!(#config.cipher(::encryptable::Cipher::#cipher));
+        }
+        config
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    if let Data::Union(_) = input.data {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "Encryptable can not be derived for unions",
+        ));
+    }
+
+    let options = Options::parse(&input)?;
+    let format = options.format();
+    let config = options.config();
+    let type_tag = match &options.tag {
+        Some(tag) => quote!(::std::option::Option::Some(#tag)),
+        None => quote!(::std::option::Option::None),
+    };
+
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    generics
+        .make_where_clause()
+        .predicates
+        .push(parse_quote!(::encryptable::FormatEncrypter<#format>: ::encryptable::Encryptable<#name #ty_generics>));
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::encryptable::Encryptable<Self> for #name #ty_generics #where_clause {
+            fn to_plaintext(data: &Self) -> ::std::result::Result<::encryptable::SecretBytes, ::encryptable::EncryptError> {
+                <::encryptable::FormatEncrypter<#format> as ::encryptable::Encryptable<Self>>::to_plaintext(data)
+            }
+
+            fn from_plaintext(plaintext: ::encryptable::SecretBytes) -> ::std::result::Result<Self, ::encryptable::DecryptError> {
+                <::encryptable::FormatEncrypter<#format> as ::encryptable::Encryptable<Self>>::from_plaintext(plaintext)
+            }
+
+            fn config() -> ::encryptable::Config {
+                #config
+            }
+
+            fn type_tag() -> ::std::option::Option<&'static str> {
+                #type_tag
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// Encrypts `self` with password
+            pub fn encrypt(&self, password: &str) -> ::std::result::Result<::encryptable::Encrypted<Self>, ::encryptable::EncryptError> {
+                <Self as ::encryptable::Encryptable<Self>>::encrypt(self, password)
+            }
+
+            /// Decrypts `Encrypted` with password
+            pub fn decrypt(data: &::encryptable::Encrypted<Self>, password: &str) -> ::std::result::Result<Self, ::encryptable::DecryptError> {
+                <Self as ::encryptable::Encryptable<Self>>::decrypt(data, password)
+            }
+        }
+    })
+}
+
+fn expand_fields(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "EncryptFields needs a struct with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "EncryptFields can only be derived for structs",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &input.generics,
+            "EncryptFields does not support generic structs",
+        ));
+    }
+
+    let options = Options::parse(&input)?;
+    if let Some(tag) = &options.tag {
+        return Err(syn::Error::new_spanned(
+            tag,
+            "tag is not supported by EncryptFields",
+        ));
+    }
+    let format = options.format();
+    let config = options.config();
+    let name = &input.ident;
+    let vis = &input.vis;
+    let sealed = options
+        .sealed
+        .clone()
+        .unwrap_or_else(|| format_ident!("{}Sealed", name));
+    let sealed_derive = &options.sealed_derive;
+
+    let mut sealed_fields = Vec::new();
+    let mut seal = Vec::new();
+    let mut unseal = Vec::new();
+    let mut first_encrypted = None;
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let field_vis = &field.vis;
+        let ty = &field.ty;
+        let encrypt = field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("encrypt"));
+
+        if encrypt {
+            first_encrypted.get_or_insert(ident);
+            // Names can not contain a nul byte, so the caller's data can not change the field
+            let field_aad = LitStr::new(&format!("{name}.{ident}\0"), ident.span());
+            sealed_fields.push(quote!(#field_vis #ident: ::encryptable::Encrypted<#ty>));
+            seal.push(quote! {
+                #ident: <::encryptable::FormatEncrypter<#format> as ::encryptable::Encryptable<#ty>>::encrypt_with_key_and_aad(
+                    &self.#ident,
+                    key,
+                    &[#field_aad.as_bytes(), aad].concat(),
+                )?
+            });
+            unseal.push(quote! {
+                #ident: <::encryptable::FormatEncrypter<#format> as ::encryptable::Encryptable<#ty>>::decrypt_with_key_and_aad(
+                    &self.#ident,
+                    key,
+                    &[#field_aad.as_bytes(), aad].concat(),
+                )?
+            });
+        } else {
+            sealed_fields.push(quote!(#field_vis #ident: #ty));
+            seal.push(quote!(#ident: ::std::clone::Clone::clone(&self.#ident)));
+            unseal.push(quote!(#ident: ::std::clone::Clone::clone(&self.#ident)));
+        }
+    }
+    let Some(first_encrypted) = first_encrypted else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "no fields are marked with #[encrypt]",
+        ));
+    };
+
+    let doc = format!("`{name}` with its `#[encrypt]` fields encrypted");
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(#(#sealed_derive),*)]
+        #vis struct #sealed {
+            #(#sealed_fields,)*
+        }
+
+        impl #name {
+            /// Encrypts the `#[encrypt]` fields with `key`
+            pub fn seal(&self, key: &::encryptable::DerivedKey) -> ::std::result::Result<#sealed, ::encryptable::EncryptError> {
+                self.seal_with_aad(key, &[])
+            }
+
+            /// Encrypts the `#[encrypt]` fields with `key`, binding them to `aad`
+            pub fn seal_with_aad(&self, key: &::encryptable::DerivedKey, aad: &[u8]) -> ::std::result::Result<#sealed, ::encryptable::EncryptError> {
+                ::std::result::Result::Ok(#sealed {
+                    #(#seal,)*
+                })
+            }
+
+            /// Derives one key from password and encrypts the `#[encrypt]` fields with it
+            pub fn seal_with_password(&self, password: &str) -> ::std::result::Result<#sealed, ::encryptable::EncryptError> {
+                self.seal(&::encryptable::DerivedKey::new(password, &#config)?)
+            }
+        }
+
+        impl #sealed {
+            /// Decrypts the encrypted fields with `key`
+            pub fn unseal(&self, key: &::encryptable::DerivedKey) -> ::std::result::Result<#name, ::encryptable::DecryptError> {
+                self.unseal_with_aad(key, &[])
+            }
+
+            /// Decrypts the encrypted fields with `key`, checking the `aad` they were sealed with
+            pub fn unseal_with_aad(&self, key: &::encryptable::DerivedKey, aad: &[u8]) -> ::std::result::Result<#name, ::encryptable::DecryptError> {
+                ::std::result::Result::Ok(#name {
+                    #(#unseal,)*
+                })
+            }
+
+            /// Derives the key from password once and decrypts the encrypted fields with it
+            pub fn unseal_with_password(&self, password: &str) -> ::std::result::Result<#name, ::encryptable::DecryptError> {
+                self.unseal(&::encryptable::DerivedKey::unlock(&self.#first_encrypted, password)?)
+            }
+        }
+    })
+}
@@ -0,0 +1,845 @@
+use std::{borrow::Cow, fmt, marker::PhantomData, str::FromStr};
+
+use base64::{engine::general_purpose, Engine};
+use bech32::{primitives::decode::CheckedHrpstring, Bech32, Hrp};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::{
+    Cipher, DecryptError, EncryptError, Encrypted, KdfAlgorithm, KdfParams, PublicKey, SecretBytes,
+    SecretKey,
+};
+
+/// First line of an age file
+const VERSION_LINE: &[u8] = b"age-encryption.org/v1";
+/// Prefix of the version line of any age file, used to tell age files apart and to report
+/// unsupported versions
+pub(crate) const VERSION_PREFIX: &[u8] = b"age-encryption.org/";
+/// Start of the line carrying the header MAC
+const MAC_PREFIX: &[u8] = b"---";
+/// Start of a stanza line
+const STANZA_PREFIX: &[u8] = b"-> ";
+/// Columns of a full stanza body line
+const BODY_COLUMNS: usize = 64;
+/// Columns of a full armor line
+const ARMOR_COLUMNS: usize = 64;
+pub(crate) const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+const ARMOR_END: &str = "-----END AGE ENCRYPTED FILE-----";
+/// Bech32 prefix of public keys
+const PUBLIC_KEY_HRP: &str = "age";
+/// Bech32 prefix of secret keys, which are written in upper case
+const SECRET_KEY_HRP: &str = "age-secret-key-";
+const X25519_TAG: &str = "X25519";
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_TAG: &str = "scrypt";
+const SCRYPT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+const FILE_KEY_LEN: usize = 16;
+/// Length of a wrapped file key, the key and a Poly1305 tag
+const WRAPPED_KEY_LEN: usize = FILE_KEY_LEN + 16;
+const PAYLOAD_NONCE_LEN: usize = 16;
+/// Plaintext bytes in each STREAM chunk except the last
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+
+/// Header of a new file with a scrypt stanza for `passphrase`, and its random file key. The
+/// header ends with the MAC line
+pub(crate) fn passphrase_header(
+    passphrase: &str,
+    kdf: &KdfParams,
+) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), EncryptError> {
+    // Anything else could not be read back from the stanza
+    let work_factor = match kdf.algorithm {
+        KdfAlgorithm::Scrypt { log_n, .. } if *kdf == KdfParams::scrypt(log_n, 8, 1) => log_n,
+        _ => {
+            return Err(EncryptError::Kdf(
+                "age needs KdfParams::scrypt(log_n, 8, 1)".into(),
+            ))
+        }
+    };
+
+    let file_key = generate_file_key();
+    let mut salt = [0u8; 16];
+    thread_rng().fill_bytes(&mut salt);
+    let wrapping_key =
+        scrypt_wrapping_key(passphrase, &salt, work_factor).map_err(EncryptError::Kdf)?;
+
+    let stanza = Stanza {
+        args: vec![
+            SCRYPT_TAG.to_string(),
+            encode_base64(&salt),
+            work_factor.to_string(),
+        ],
+        body: wrap_file_key(&wrapping_key, &file_key)?,
+    };
+    Ok((write_header(&file_key, &[stanza]), file_key_vec(&file_key)))
+}
+
+/// Header of a new file encrypted to the public keys of `recipients`, and its random file key
+pub(crate) fn recipients_header(
+    recipients: &[PublicKey],
+) -> Result<(Vec<u8>, Zeroizing<Vec<u8>>), EncryptError> {
+    if recipients.is_empty() {
+        return Err(EncryptError::Kdf("at least one recipient is needed".into()));
+    }
+
+    let file_key = generate_file_key();
+    let stanzas = recipients
+        .iter()
+        .map(|recipient| {
+            let ephemeral = SecretKey::generate();
+            let share = ephemeral.public_key();
+            let wrapping_key = x25519_wrapping_key(&ephemeral, recipient, &share, recipient)
+                .map_err(|e| EncryptError::Kdf(e.into()))?;
+            Ok(Stanza {
+                args: vec![X25519_TAG.to_string(), encode_base64(share.as_bytes())],
+                body: wrap_file_key(&wrapping_key, &file_key)?,
+            })
+        })
+        .collect::<Result<Vec<_>, EncryptError>>()?;
+    Ok((write_header(&file_key, &stanzas), file_key_vec(&file_key)))
+}
+
+/// Unwraps the file key from the scrypt stanza of `header` with `passphrase` and checks the
+/// header MAC. The work factor is checked by the caller, it is the kdf of the `Encrypted`
+pub(crate) fn unlock_with_passphrase(
+    header: &[u8],
+    passphrase: &str,
+) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+    let header = Header::parse(header)?;
+    let Some(stanza) = header.stanzas.iter().find(|s| s.args[0] == SCRYPT_TAG) else {
+        return Err(DecryptError::KeyMismatch);
+    };
+    let (salt, work_factor) = scrypt_args(stanza)?;
+
+    let wrapping_key =
+        scrypt_wrapping_key(passphrase, &salt, work_factor).map_err(DecryptError::Kdf)?;
+    let file_key =
+        unwrap_file_key(&wrapping_key, &stanza.body)?.ok_or(DecryptError::WrongPassword)?;
+    header.verify(&file_key)?;
+    Ok(file_key_vec(&file_key))
+}
+
+/// Unwraps the file key from the X25519 stanza of `header` for `secret_key` and checks the
+/// header MAC
+pub(crate) fn unlock_with_secret_key(
+    header: &[u8],
+    secret_key: &SecretKey,
+) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+    let header = Header::parse(header)?;
+
+    let recipient = secret_key.public_key();
+    for stanza in header.stanzas.iter().filter(|s| s.args[0] == X25519_TAG) {
+        let [_, share] = &stanza.args[..] else {
+            return Err(DecryptError::Malformed("X25519 stanza needs one argument"));
+        };
+        let share = decode_base64(share)
+            .ok()
+            .and_then(|share| <[u8; 32]>::try_from(share).ok())
+            .ok_or(DecryptError::Malformed("invalid X25519 share"))?;
+        let share = PublicKey::from_bytes(share);
+        let wrapping_key = x25519_wrapping_key(secret_key, &share, &share, &recipient)
+            .map_err(DecryptError::Malformed)?;
+
+        if let Some(file_key) = unwrap_file_key(&wrapping_key, &stanza.body)? {
+            header.verify(&file_key)?;
+            return Ok(file_key_vec(&file_key));
+        }
+    }
+    Err(DecryptError::Authentication)
+}
+
+/// Encrypts the payload with a random nonce: the nonce followed by the STREAM chunks
+pub(crate) fn encrypt_payload(file_key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, EncryptError> {
+    let file_key = to_file_key(file_key).ok_or(EncryptError::Cipher("invalid file key".into()))?;
+    let mut nonce = [0u8; PAYLOAD_NONCE_LEN];
+    thread_rng().fill_bytes(&mut nonce);
+
+    let chunk_count = plaintext.len().div_ceil(CHUNK_SIZE).max(1);
+    let mut out = Vec::with_capacity(PAYLOAD_NONCE_LEN + plaintext.len() + chunk_count * TAG_LEN);
+    out.extend_from_slice(&nonce);
+
+    let payload = payload_cipher(file_key, &nonce);
+    for (i, chunk) in plaintext
+        .chunks(CHUNK_SIZE)
+        .chain(plaintext.is_empty().then_some(&[][..]))
+        .enumerate()
+    {
+        let nonce = chunk_nonce(i as u64, i + 1 == chunk_count);
+        let ciphertext = payload
+            .encrypt(&nonce.into(), chunk)
+            .map_err(|_| EncryptError::Cipher("encryption failed".into()))?;
+        out.extend_from_slice(&ciphertext);
+    }
+    Ok(out)
+}
+
+/// Decrypts a payload written by [`encrypt_payload`]. The header MAC was checked when the file
+/// key was unwrapped, so a chunk failing authentication means the payload was changed
+pub(crate) fn decrypt_payload(
+    file_key: &[u8],
+    payload: &[u8],
+) -> Result<SecretBytes, DecryptError> {
+    let file_key = to_file_key(file_key).ok_or(DecryptError::KeyMismatch)?;
+    if payload.len() < PAYLOAD_NONCE_LEN {
+        return Err(DecryptError::Malformed("payload nonce is truncated"));
+    }
+
+    let (nonce, mut chunks) = payload.split_at(PAYLOAD_NONCE_LEN);
+    let payload = payload_cipher(file_key, nonce);
+    let mut out = Zeroizing::new(Vec::with_capacity(chunks.len()));
+    let mut i = 0;
+    loop {
+        let last = chunks.len() <= CHUNK_SIZE + TAG_LEN;
+        let (chunk, rest) = chunks.split_at(chunks.len().min(CHUNK_SIZE + TAG_LEN));
+        if chunk.len() < TAG_LEN {
+            return Err(DecryptError::Malformed("payload chunk is truncated"));
+        }
+        if last && chunk.len() == TAG_LEN && i > 0 {
+            return Err(DecryptError::Malformed("final payload chunk is empty"));
+        }
+
+        let plaintext = Zeroizing::new(
+            payload
+                .decrypt(&chunk_nonce(i, last).into(), chunk)
+                .map_err(|_| DecryptError::Tampered)?,
+        );
+        out.extend_from_slice(&plaintext);
+
+        if last {
+            break;
+        }
+        chunks = rest;
+        i += 1;
+    }
+
+    Ok(SecretBytes::new(std::mem::take(&mut out)))
+}
+
+/// Writes the stanzas and the header MAC
+fn write_header(file_key: &[u8; FILE_KEY_LEN], stanzas: &[Stanza]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(256);
+    out.extend_from_slice(VERSION_LINE);
+    out.push(b'\n');
+    for stanza in stanzas {
+        out.extend_from_slice(STANZA_PREFIX);
+        out.extend_from_slice(stanza.args.join(" ").as_bytes());
+        out.push(b'\n');
+
+        let body = encode_base64(&stanza.body);
+        let mut lines = body.as_bytes().chunks(BODY_COLUMNS);
+        for line in lines.by_ref() {
+            out.extend_from_slice(line);
+            out.push(b'\n');
+        }
+        // Every stanza ends with a short line, which is empty if the last one was full
+        if body.len().is_multiple_of(BODY_COLUMNS) {
+            out.push(b'\n');
+        }
+    }
+    out.extend_from_slice(MAC_PREFIX);
+    let mac = header_mac(file_key, &out).finalize().into_bytes();
+    out.push(b' ');
+    out.extend_from_slice(encode_base64(&mac).as_bytes());
+    out.push(b'\n');
+    out
+}
+
+/// An age file as an [`Encrypted`]: the header is stored as the salt, as it identifies the file
+/// key, and the payload as the data. Both are written back unchanged by [`Encrypted::to_bytes`]
+impl<T> Encrypted<T> {
+    /// Reads a binary age file. The kdf is the scrypt work factor of a passphrase file, or
+    /// X25519 for a file encrypted to recipients
+    pub(crate) fn from_age_file(file: &[u8]) -> Result<Self, DecryptError> {
+        let header = Header::parse(file)?;
+        let kdf = match header.stanzas.iter().find(|s| s.args[0] == SCRYPT_TAG) {
+            Some(stanza) => KdfParams::scrypt(scrypt_args(stanza)?.1, 8, 1),
+            None => KdfParams::x25519(),
+        };
+        let (header, payload) = file.split_at(header.len);
+        if payload.len() < PAYLOAD_NONCE_LEN {
+            return Err(DecryptError::Malformed("payload nonce is truncated"));
+        }
+
+        Ok(Self {
+            cipher: Cipher::Age,
+            kdf,
+            salt: header.to_vec(),
+            nonce: Vec::new(),
+            data: payload.to_vec(),
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        })
+    }
+}
+
+impl fmt::Display for PublicKey {
+    /// Formats the key as an age recipient, `age1...`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hrp = Hrp::parse_unchecked(PUBLIC_KEY_HRP);
+        bech32::encode_lower_to_fmt::<Bech32, _>(f, hrp, self.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl FromStr for PublicKey {
+    type Err = DecryptError;
+
+    /// Parses an age recipient, `age1...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_bech32(s, PUBLIC_KEY_HRP)
+            .map(|bytes| Self::from_bytes(*bytes))
+            .ok_or(DecryptError::Malformed("invalid age public key"))
+    }
+}
+
+impl SecretKey {
+    /// Formats the key as an age identity, `AGE-SECRET-KEY-1...`. Requires the `age` feature
+    pub fn to_age_string(&self) -> Zeroizing<String> {
+        let hrp = Hrp::parse_unchecked(SECRET_KEY_HRP);
+        Zeroizing::new(
+            bech32::encode_upper::<Bech32>(hrp, &*self.to_bytes())
+                .expect("a 32 byte key fits in bech32"),
+        )
+    }
+}
+
+impl FromStr for SecretKey {
+    type Err = DecryptError;
+
+    /// Parses an age identity, `AGE-SECRET-KEY-1...`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        decode_bech32(s, SECRET_KEY_HRP)
+            .map(|bytes| Self::from_bytes(*bytes))
+            .ok_or(DecryptError::Malformed("invalid age secret key"))
+    }
+}
+
+/// Decodes a bech32 string holding 32 bytes with the prefix `hrp`
+fn decode_bech32(s: &str, hrp: &str) -> Option<Zeroizing<[u8; 32]>> {
+    let checked = CheckedHrpstring::new::<Bech32>(s).ok()?;
+    if checked.hrp().to_lowercase() != hrp {
+        return None;
+    }
+    let bytes = Zeroizing::new(checked.byte_iter().collect::<Vec<u8>>());
+    let mut out = Zeroizing::new([0u8; 32]);
+    if bytes.len() != out.len() {
+        return None;
+    }
+    out.copy_from_slice(&bytes);
+    Some(out)
+}
+
+/// A recipient stanza, the first argument is its type
+struct Stanza {
+    args: Vec<String>,
+    body: Vec<u8>,
+}
+
+/// Parsed header of an age file
+struct Header<'a> {
+    stanzas: Vec<Stanza>,
+    /// Header up to and including `---`, which the MAC covers
+    authenticated: &'a [u8],
+    mac: Vec<u8>,
+    /// Length of the header including the MAC line, the payload follows it
+    len: usize,
+}
+
+impl<'a> Header<'a> {
+    fn parse(file: &'a [u8]) -> Result<Self, DecryptError> {
+        let mut lines = Lines { file, pos: 0 };
+
+        let version = lines.next()?;
+        if version != VERSION_LINE {
+            return Err(if version.starts_with(VERSION_PREFIX) {
+                DecryptError::Malformed("unsupported age version")
+            } else {
+                DecryptError::Malformed("not an age file")
+            });
+        }
+
+        let mut stanzas = Vec::new();
+        loop {
+            let start = lines.pos;
+            let line = lines.next()?;
+
+            if let Some(args) = line.strip_prefix(STANZA_PREFIX) {
+                let args = args
+                    .split(|&b| b == b' ')
+                    .map(|arg| {
+                        if arg.is_empty() || !arg.iter().all(|b| (0x21..=0x7e).contains(b)) {
+                            return Err(DecryptError::Malformed("invalid stanza argument"));
+                        }
+                        // Checked to be printable ascii above
+                        Ok(String::from_utf8(arg.to_vec()).unwrap())
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut body = String::new();
+                loop {
+                    let line = lines.next()?;
+                    if line.len() > BODY_COLUMNS {
+                        return Err(DecryptError::Malformed("stanza body line is too long"));
+                    }
+                    body.push_str(
+                        std::str::from_utf8(line)
+                            .map_err(|_| DecryptError::Malformed("invalid stanza body"))?,
+                    );
+                    if line.len() < BODY_COLUMNS {
+                        break;
+                    }
+                }
+                let body = decode_base64(&body)
+                    .map_err(|_| DecryptError::Malformed("invalid stanza body"))?;
+                stanzas.push(Stanza { args, body });
+            } else if let Some(mac) = line.strip_prefix(MAC_PREFIX) {
+                let mac = mac
+                    .strip_prefix(b" ")
+                    .and_then(|mac| std::str::from_utf8(mac).ok())
+                    .and_then(|mac| decode_base64(mac).ok())
+                    .filter(|mac| mac.len() == 32)
+                    .ok_or(DecryptError::Malformed("invalid header MAC"))?;
+
+                return Self::new(stanzas, &file[..start + MAC_PREFIX.len()], mac, lines.pos);
+            } else {
+                return Err(DecryptError::Malformed("invalid header line"));
+            }
+        }
+    }
+
+    fn new(
+        stanzas: Vec<Stanza>,
+        authenticated: &'a [u8],
+        mac: Vec<u8>,
+        len: usize,
+    ) -> Result<Self, DecryptError> {
+        if stanzas.is_empty() {
+            return Err(DecryptError::Malformed("age file has no recipients"));
+        }
+        if stanzas.len() > 1 && stanzas.iter().any(|s| s.args[0] == SCRYPT_TAG) {
+            return Err(DecryptError::Malformed(
+                "scrypt stanzas must be alone in the header",
+            ));
+        }
+
+        Ok(Self {
+            stanzas,
+            authenticated,
+            mac,
+            len,
+        })
+    }
+
+    /// Checks the header MAC with `file_key`. The file key was unwrapped, so a wrong MAC means
+    /// the header was changed
+    fn verify(&self, file_key: &[u8; FILE_KEY_LEN]) -> Result<(), DecryptError> {
+        header_mac(file_key, self.authenticated)
+            .verify_slice(&self.mac)
+            .map_err(|_| DecryptError::Tampered)
+    }
+}
+
+/// Salt and work factor of a scrypt stanza
+fn scrypt_args(stanza: &Stanza) -> Result<(Vec<u8>, u8), DecryptError> {
+    let [_, salt, work_factor] = &stanza.args[..] else {
+        return Err(DecryptError::Malformed("scrypt stanza needs two arguments"));
+    };
+    let salt = decode_base64(salt)
+        .ok()
+        .filter(|salt| salt.len() == 16)
+        .ok_or(DecryptError::Malformed("invalid scrypt salt"))?;
+    Ok((salt, parse_work_factor(work_factor)?))
+}
+
+/// Splits the header into lines ending with `\n`
+struct Lines<'a> {
+    file: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Lines<'a> {
+    fn next(&mut self) -> Result<&'a [u8], DecryptError> {
+        let rest = &self.file[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(DecryptError::Malformed("age header is truncated"))?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+/// Parses a scrypt work factor, a decimal number without leading zeros or signs
+fn parse_work_factor(work_factor: &str) -> Result<u8, DecryptError> {
+    let invalid = DecryptError::Malformed("invalid scrypt work factor");
+    if !work_factor.bytes().all(|b| b.is_ascii_digit()) || work_factor.starts_with('0') {
+        return Err(invalid);
+    }
+    match work_factor.parse::<u8>() {
+        Ok(work_factor) if work_factor > 0 => Ok(work_factor),
+        _ => Err(invalid),
+    }
+}
+
+/// Encodes `data` as ASCII armor
+pub(crate) fn armor(data: &[u8]) -> String {
+    let encoded = general_purpose::STANDARD.encode(data);
+    let mut out = String::with_capacity(encoded.len() + 128);
+    out.push_str(ARMOR_BEGIN);
+    out.push('\n');
+    for line in encoded.as_bytes().chunks(ARMOR_COLUMNS) {
+        // base64 is ascii
+        out.push_str(std::str::from_utf8(line).unwrap());
+        out.push('\n');
+    }
+    out.push_str(ARMOR_END);
+    out.push('\n');
+    out
+}
+
+/// Removes the ASCII armor if `file` is armored
+pub(crate) fn dearmor(file: &[u8]) -> Result<Cow<'_, [u8]>, DecryptError> {
+    let trimmed = file.trim_ascii();
+    if !trimmed.starts_with(ARMOR_BEGIN.as_bytes()) {
+        return Ok(Cow::Borrowed(file));
+    }
+    let invalid = || DecryptError::Malformed("invalid age armor");
+    let text = std::str::from_utf8(trimmed).map_err(|_| invalid())?;
+
+    let mut lines = text
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line));
+    if lines.next() != Some(ARMOR_BEGIN) {
+        return Err(invalid());
+    }
+
+    let mut encoded = String::new();
+    let mut ended = false;
+    for line in lines.by_ref() {
+        if line == ARMOR_END {
+            ended = true;
+            break;
+        }
+        // Only the last line can be shorter than a full line
+        if line.is_empty()
+            || line.len() > ARMOR_COLUMNS
+            || !encoded.len().is_multiple_of(ARMOR_COLUMNS)
+        {
+            return Err(invalid());
+        }
+        encoded.push_str(line);
+    }
+    if !ended || lines.next().is_some() {
+        return Err(invalid());
+    }
+
+    general_purpose::STANDARD
+        .decode(encoded)
+        .map(Cow::Owned)
+        .map_err(|_| invalid())
+}
+
+/// Canonical base64 without padding, as used in the header
+fn encode_base64(data: &[u8]) -> String {
+    general_purpose::STANDARD_NO_PAD.encode(data)
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    general_purpose::STANDARD_NO_PAD.decode(data)
+}
+
+fn generate_file_key() -> Zeroizing<[u8; FILE_KEY_LEN]> {
+    let mut file_key = Zeroizing::new([0u8; FILE_KEY_LEN]);
+    thread_rng().fill_bytes(file_key.as_mut());
+    file_key
+}
+
+/// The file key as the key of a [`DerivedKey`](crate::DerivedKey)
+fn file_key_vec(file_key: &[u8; FILE_KEY_LEN]) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(file_key.to_vec())
+}
+
+fn to_file_key(key: &[u8]) -> Option<&[u8; FILE_KEY_LEN]> {
+    key.try_into().ok()
+}
+
+/// HKDF Sha256 with `salt` and `info`, giving a 32 byte key
+fn hkdf(ikm: &[u8], salt: &[u8], info: &[u8]) -> Zeroizing<[u8; 32]> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(salt), ikm)
+        .expand(info, key.as_mut())
+        .expect("32 bytes is a valid hkdf output length");
+    key
+}
+
+/// Agrees on the key wrapping the file key of an X25519 stanza
+fn x25519_wrapping_key(
+    secret_key: &SecretKey,
+    public_key: &PublicKey,
+    share: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<Zeroizing<[u8; 32]>, &'static str> {
+    let shared = secret_key.diffie_hellman(public_key)?;
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(share.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+    Ok(hkdf(&*shared, &salt, X25519_LABEL))
+}
+
+/// Derives the key wrapping the file key of a scrypt stanza
+fn scrypt_wrapping_key(
+    passphrase: &str,
+    salt: &[u8],
+    work_factor: u8,
+) -> Result<Zeroizing<[u8; 32]>, crate::BoxError> {
+    let mut label = SCRYPT_LABEL.to_vec();
+    label.extend_from_slice(salt);
+    let params = scrypt::Params::new(work_factor, 8, 1, 32)?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    scrypt::scrypt(passphrase.as_bytes(), &label, &params, key.as_mut())?;
+    Ok(key)
+}
+
+fn wrap_file_key(
+    wrapping_key: &[u8; 32],
+    file_key: &[u8; FILE_KEY_LEN],
+) -> Result<Vec<u8>, EncryptError> {
+    ChaCha20Poly1305::new(wrapping_key.into())
+        .encrypt(&[0u8; 12].into(), &file_key[..])
+        .map_err(|_| EncryptError::Cipher("encryption failed".into()))
+}
+
+/// Unwraps the file key from a stanza body, `None` if the key does not match
+fn unwrap_file_key(
+    wrapping_key: &[u8; 32],
+    body: &[u8],
+) -> Result<Option<Zeroizing<[u8; FILE_KEY_LEN]>>, DecryptError> {
+    // Checked before decrypting so a longer key is never accepted
+    if body.len() != WRAPPED_KEY_LEN {
+        return Err(DecryptError::Malformed(
+            "wrapped file key has the wrong length",
+        ));
+    }
+    let Ok(key) = ChaCha20Poly1305::new(wrapping_key.into()).decrypt(&[0u8; 12].into(), body)
+    else {
+        return Ok(None);
+    };
+    let key = Zeroizing::new(key);
+    let mut file_key = Zeroizing::new([0u8; FILE_KEY_LEN]);
+    file_key.copy_from_slice(&key);
+    Ok(Some(file_key))
+}
+
+fn header_mac(file_key: &[u8; FILE_KEY_LEN], header: &[u8]) -> Hmac<Sha256> {
+    let key = hkdf(file_key, &[], b"header");
+    let mut mac =
+        <Hmac<Sha256> as Mac>::new_from_slice(&*key).expect("hmac accepts any key length");
+    mac.update(header);
+    mac
+}
+
+fn payload_cipher(file_key: &[u8; FILE_KEY_LEN], nonce: &[u8]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new((&*hkdf(file_key, nonce, b"payload")).into())
+}
+
+/// STREAM nonce, a big endian counter followed by a flag for the last chunk
+fn chunk_nonce(counter: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&counter.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BytesEncrypter, Cipher, Config, DecryptError, DerivedKey, EncryptError, Encryptable,
+        Encrypted, KdfLimits, KdfParams, PublicKey, SecretBytes, SecretKey,
+    };
+
+    #[test]
+    fn round_trip() {
+        let secret_key = SecretKey::generate();
+        let data = SecretBytes::new((0..200_000).map(|i| i as u8).collect());
+        let config = Config::new()
+            .cipher(Cipher::Age)
+            .kdf(KdfParams::scrypt(4, 8, 1));
+
+        for armor in [false, true] {
+            // Files go through their binary or armored form
+            let reread = |encrypted: Encrypted| match armor {
+                false => Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap(),
+                true => encrypted.to_string().parse().unwrap(),
+            };
+
+            for len in [0, 1, 64 * 1024, 200_000] {
+                let data = SecretBytes::from(&data[..len]);
+                let encrypted = reread(
+                    BytesEncrypter::encrypt_to_recipients(
+                        &data,
+                        &[secret_key.public_key()],
+                        &config,
+                    )
+                    .unwrap(),
+                );
+                assert_eq!(encrypted.kdf(), &KdfParams::x25519());
+                assert_eq!(
+                    BytesEncrypter::decrypt_with_secret_key(&encrypted, &secret_key).unwrap(),
+                    data
+                );
+            }
+
+            let encrypted = reread(
+                BytesEncrypter::encrypt_with(&SecretBytes::from("test"), "password", &config)
+                    .unwrap(),
+            );
+            assert_eq!(encrypted.kdf(), &KdfParams::scrypt(4, 8, 1));
+            assert_eq!(encrypted.created_at(), None);
+            assert_eq!(
+                &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+                b"test"
+            );
+            assert!(matches!(
+                BytesEncrypter::decrypt(&encrypted, "wrong"),
+                Err(DecryptError::WrongPassword)
+            ));
+            assert!(matches!(
+                DerivedKey::unlock_with_limits(
+                    &encrypted,
+                    "password",
+                    &KdfLimits::new().max_log_n(3)
+                ),
+                Err(DecryptError::Malformed(_))
+            ));
+            assert!(matches!(
+                BytesEncrypter::decrypt_with_secret_key(&encrypted, &secret_key),
+                Err(DecryptError::Authentication)
+            ));
+            assert!(matches!(
+                BytesEncrypter::decrypt_with_aad(&encrypted, "password", b"aad"),
+                Err(DecryptError::Authentication)
+            ));
+        }
+    }
+
+    #[test]
+    fn derived_key() {
+        let config = Config::new()
+            .cipher(Cipher::Age)
+            .kdf(KdfParams::scrypt(4, 8, 1));
+        let key = DerivedKey::new("password", &config).unwrap();
+        let first = BytesEncrypter::encrypt_with_key(&SecretBytes::from("first"), &key).unwrap();
+        let second = BytesEncrypter::encrypt_with_key(&SecretBytes::from("second"), &key).unwrap();
+
+        // Both files share the header of the key, each payload has its own nonce
+        assert_eq!(first.salt(), second.salt());
+        assert_ne!(first.ciphertext()[..16], second.ciphertext()[..16]);
+        let unlocked = DerivedKey::unlock(&first, "password").unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_key(&second, &unlocked).unwrap(),
+            b"second"
+        );
+
+        // Changing the header breaks its MAC
+        let mut file = first.to_bytes().unwrap();
+        let mac = first.salt().len() - 3;
+        file[mac] = if file[mac] == b'A' { b'B' } else { b'A' };
+        assert!(matches!(
+            BytesEncrypter::decrypt(&Encrypted::from_bytes(&file).unwrap(), "password"),
+            Err(DecryptError::Tampered)
+        ));
+        let mut file = first.to_bytes().unwrap();
+        *file.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            BytesEncrypter::decrypt(&Encrypted::from_bytes(&file).unwrap(), "password"),
+            Err(DecryptError::Tampered)
+        ));
+    }
+
+    #[test]
+    fn unsupported() {
+        let config = Config::new()
+            .cipher(Cipher::Age)
+            .kdf(KdfParams::scrypt(4, 8, 1));
+        let data = SecretBytes::from("test");
+
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(&data, "password", &Config::new().cipher(Cipher::Age)),
+            Err(EncryptError::Kdf(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::encrypt_with_aad(&data, "password", &config, b"aad"),
+            Err(EncryptError::Cipher(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::encrypt_with_key_slots(&data, &["password"], &config),
+            Err(EncryptError::Cipher(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(
+                &data,
+                "password",
+                &config
+                    .clone()
+                    .expires_after(std::time::Duration::from_secs(60))
+            ),
+            Err(EncryptError::Cipher(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::encrypt_to_recipients(&data, &[], &config),
+            Err(EncryptError::Kdf(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde() {
+        let config = Config::new()
+            .cipher(Cipher::Age)
+            .kdf(KdfParams::scrypt(4, 8, 1));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from("test"), "password", &config).unwrap();
+
+        let json = serde_json::to_string(&encrypted).unwrap();
+        let decoded: Encrypted = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, encrypted);
+        // The kdf is read from the header, not trusted from the other fields
+        let changed = json.replace("\"log_n\":4", "\"log_n\":5");
+        assert_ne!(changed, json);
+        let decoded: Encrypted = serde_json::from_str(&changed).unwrap();
+        assert_eq!(decoded.kdf(), &KdfParams::scrypt(4, 8, 1));
+    }
+
+    #[test]
+    fn keys() {
+        let secret_key: SecretKey =
+            "AGE-SECRET-KEY-1XMWWC06LY3EE5RYTXM9MFLAZ2U56JJJ36S0MYPDRWSVLUL66MV4QX3S7F6"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            &**secret_key.to_age_string(),
+            "AGE-SECRET-KEY-1XMWWC06LY3EE5RYTXM9MFLAZ2U56JJJ36S0MYPDRWSVLUL66MV4QX3S7F6"
+        );
+
+        let public_key = secret_key.public_key().to_string();
+        assert!(public_key.starts_with("age1"));
+        assert_eq!(
+            public_key.parse::<PublicKey>().unwrap(),
+            secret_key.public_key()
+        );
+        assert!(public_key[1..].parse::<PublicKey>().is_err());
+        assert!(public_key.parse::<SecretKey>().is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::{fmt, str::FromStr};
+
+use base64::{engine::general_purpose, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::{DecryptError, EncryptError, Encrypted};
+
+const ARMOR_BEGIN: &str = "-----BEGIN ENCRYPTABLE-----";
+const ARMOR_END: &str = "-----END ENCRYPTABLE-----";
+/// Columns of a full armor line
+const ARMOR_COLUMNS: usize = 64;
+/// Start of the checksum line
+const CHECKSUM_PREFIX: char = '=';
+/// Bytes of the Sha256 of the binary format used as checksum
+const CHECKSUM_LEN: usize = 3;
+
+/// Checksum of the armored bytes, catches copy and paste mistakes before decrypting
+fn checksum(bytes: &[u8]) -> String {
+    general_purpose::STANDARD.encode(&Sha256::digest(bytes)[..CHECKSUM_LEN])
+}
+
+impl<T> Encrypted<T> {
+    /// Encodes the ASCII armored form: the binary format from [`Encrypted::to_bytes`] as base64
+    /// lines between `-----BEGIN ENCRYPTABLE-----` and `-----END ENCRYPTABLE-----`, followed by a
+    /// `=` line holding a checksum, the first 3 bytes of its Sha256. Parse it back with
+    /// [`str::parse`]. `Display` writes the same text.
+    ///
+    /// [`Cipher::Age`](crate::Cipher::Age) files are written in the age armor instead, between
+    /// `-----BEGIN AGE ENCRYPTED FILE-----` and `-----END AGE ENCRYPTED FILE-----`.
+    ///
+    /// # Errors
+    /// Fails where [`Encrypted::to_bytes`] fails
+    ///
+    /// ```
+    /// use encryptable::{BytesEncrypter, Config, Encryptable, Encrypted, KdfParams, SecretBytes};
+    ///
+    /// let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+    /// let encrypted =
+    ///     BytesEncrypter::encrypt_with(&SecretBytes::from("test"), "password", &config).unwrap();
+    /// let armored = encrypted.to_armored().unwrap();
+    /// assert!(armored.starts_with("-----BEGIN ENCRYPTABLE-----\n"));
+    /// assert_eq!(armored.parse::<Encrypted>().unwrap(), encrypted);
+    /// ```
+    pub fn to_armored(&self) -> Result<String, EncryptError> {
+        let bytes = self.to_bytes()?;
+        #[cfg(feature = "age")]
+        if self.cipher == crate::Cipher::Age {
+            return Ok(crate::age::armor(&bytes));
+        }
+        let encoded = general_purpose::STANDARD.encode(&bytes);
+
+        let mut out = String::with_capacity(encoded.len() * 65 / 64 + 96);
+        out.push_str(ARMOR_BEGIN);
+        out.push('\n');
+        for line in encoded.as_bytes().chunks(ARMOR_COLUMNS) {
+            // base64 is ascii
+            out.push_str(std::str::from_utf8(line).unwrap());
+            out.push('\n');
+        }
+        out.push(CHECKSUM_PREFIX);
+        out.push_str(&checksum(&bytes));
+        out.push('\n');
+        out.push_str(ARMOR_END);
+        out.push('\n');
+        Ok(out)
+    }
+}
+
+/// Writes the ASCII armored form, see [`Encrypted::to_armored`]. Every `Encrypted` made by
+/// encrypting or deserializing can be encoded, so this does not fail
+impl<T> fmt::Display for Encrypted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_armored().map_err(|_| fmt::Error)?)
+    }
+}
+
+/// Parses the ASCII armored form written by `Display`. Whitespace around the armor and `\r\n`
+/// line endings are accepted. With the `age` feature, age armor is read too
+impl<T> FromStr for Encrypted<T> {
+    type Err = DecryptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        #[cfg(feature = "age")]
+        if s.trim_start().starts_with(crate::age::ARMOR_BEGIN) {
+            return Self::from_age_file(&crate::age::dearmor(s.as_bytes())?);
+        }
+        let invalid = || DecryptError::Malformed("invalid armor");
+        let mut lines = s.trim().lines().map(str::trim_end);
+        if lines.next() != Some(ARMOR_BEGIN) {
+            return Err(invalid());
+        }
+
+        let mut encoded = String::new();
+        let mut checksum_line = None;
+        let mut ended = false;
+        for line in lines.by_ref() {
+            if line == ARMOR_END {
+                ended = true;
+                break;
+            }
+            if checksum_line.is_some() {
+                return Err(invalid());
+            }
+            match line.strip_prefix(CHECKSUM_PREFIX) {
+                Some(line) => checksum_line = Some(line),
+                None => encoded.push_str(line),
+            }
+        }
+        if !ended || lines.next().is_some() {
+            return Err(invalid());
+        }
+
+        let bytes = general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| invalid())?;
+        if checksum_line != Some(checksum(&bytes).as_str()) {
+            return Err(DecryptError::Malformed("armor checksum does not match"));
+        }
+        Self::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::{Cipher, DecryptError, EncryptError, Encrypted, KdfParams};
+
+    #[test]
+    fn armor() {
+        let encrypted = Encrypted {
+            cipher: Cipher::XChaCha20Poly1305,
+            kdf: KdfParams::default(),
+            salt: vec![1; 16],
+            nonce: vec![2; 24],
+            data: vec![3; 100],
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        };
+        let armored = encrypted.to_armored().unwrap();
+        assert_eq!(encrypted.to_string(), armored);
+        assert!(armored.lines().all(|line| line.len() <= 64));
+        assert_eq!(armored.parse::<Encrypted>().unwrap(), encrypted);
+
+        let crlf = format!("\n  {}  \n", armored.replace('\n', "\r\n"));
+        assert_eq!(crlf.parse::<Encrypted>().unwrap(), encrypted);
+
+        // One changed character in the body fails the checksum
+        let mut lines: Vec<String> = armored.lines().map(str::to_string).collect();
+        let replacement = if &lines[1][10..11] == "A" { "B" } else { "A" };
+        lines[1].replace_range(10..11, replacement);
+        assert!(matches!(
+            lines.join("\n").parse::<Encrypted>(),
+            Err(DecryptError::Malformed("armor checksum does not match"))
+        ));
+
+        for bad in [
+            "",
+            "not armored",
+            &armored.replace("-----END ENCRYPTABLE-----\n", ""),
+            &armored.replace("BEGIN", "START"),
+        ] {
+            assert!(matches!(
+                bad.parse::<Encrypted>(),
+                Err(DecryptError::Malformed(_))
+            ));
+        }
+
+        let mut too_long = encrypted;
+        too_long.type_tag = Some("x".repeat(usize::from(u16::MAX) + 1));
+        assert!(matches!(
+            too_long.to_armored(),
+            Err(EncryptError::Serialization(_))
+        ));
+    }
+}
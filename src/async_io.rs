@@ -0,0 +1,378 @@
+use std::{
+    io,
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    task::spawn_blocking,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    stream::{parse_header, StreamOpener, StreamSealer, READ_BUFFER_LEN},
+    Config, DecryptError, DerivedKey, EncryptError, Encrypted, KdfLimits, SecretKey,
+};
+
+impl DerivedKey {
+    /// Like [`DerivedKey::new`], but runs the kdf on tokio's blocking thread pool so it does not
+    /// stall the runtime
+    pub async fn new_async(password: &str, config: &Config) -> Result<Self, EncryptError> {
+        let password = Zeroizing::new(password.to_string());
+        let config = config.clone();
+        spawn_blocking(move || Self::new(&password, &config))
+            .await
+            .map_err(|e| EncryptError::Kdf(e.into()))?
+    }
+
+    /// Like [`DerivedKey::unlock`], but runs the kdf on tokio's blocking thread pool so it does
+    /// not stall the runtime
+    pub async fn unlock_async<T>(
+        encrypted: &Encrypted<T>,
+        password: &str,
+    ) -> Result<Self, DecryptError> {
+        Self::unlock_async_with_limits(encrypted, password, KdfLimits::default()).await
+    }
+
+    /// Like [`DerivedKey::unlock_with_limits`], but runs the kdf on tokio's blocking thread pool
+    pub async fn unlock_async_with_limits<T>(
+        encrypted: &Encrypted<T>,
+        password: &str,
+        limits: KdfLimits,
+    ) -> Result<Self, DecryptError> {
+        let password = Zeroizing::new(password.to_string());
+        // Only the header is needed to derive the key
+        let header: Encrypted = Encrypted {
+            cipher: encrypted.cipher,
+            kdf: encrypted.kdf,
+            salt: encrypted.salt.clone(),
+            nonce: encrypted.nonce.clone(),
+            data: Vec::new(),
+            slots: encrypted.slots.clone(),
+            type_tag: None,
+            key_check: encrypted.key_check.clone(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        };
+        spawn_blocking(move || Self::unlock_with_limits(&header, &password, &limits))
+            .await
+            .map_err(|e| DecryptError::Kdf(e.into()))?
+    }
+}
+
+/// Async version of [`EncryptWriter`](crate::EncryptWriter) for tokio, writing the same format.
+///
+/// [`AsyncWriteExt::shutdown`](tokio::io::AsyncWriteExt::shutdown) writes the last chunk and
+/// must be called, dropping the writer without it leaves a stream that fails to decrypt.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use encryptable::{AsyncDecryptReader, AsyncEncryptWriter, Config, KdfParams};
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+/// let mut writer = AsyncEncryptWriter::new(Vec::new(), "password", &config)
+///     .await
+///     .unwrap();
+/// writer.write_all(b"test").await.unwrap();
+/// writer.shutdown().await.unwrap();
+/// let encrypted = writer.into_inner();
+///
+/// let mut reader = AsyncDecryptReader::new(&encrypted[..], "password")
+///     .await
+///     .unwrap();
+/// let mut decrypted = Vec::new();
+/// reader.read_to_end(&mut decrypted).await.unwrap();
+/// assert_eq!(decrypted, b"test");
+/// # }
+/// ```
+pub struct AsyncEncryptWriter<W: AsyncWrite + Unpin> {
+    inner: W,
+    sealer: StreamSealer,
+    out: Vec<u8>,
+    written: usize,
+    finished: bool,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncEncryptWriter<W> {
+    /// Derives a key from `password` on the blocking thread pool, using the cipher and kdf of
+    /// `config`, and encrypts into `inner` with it. Nothing is written until the first write
+    pub async fn new(inner: W, password: &str, config: &Config) -> Result<Self, EncryptError> {
+        Self::with_key(inner, &DerivedKey::new_async(password, config).await?)
+    }
+
+    /// Encrypts into `inner` with an already derived `key`
+    pub fn with_key(inner: W, key: &DerivedKey) -> Result<Self, EncryptError> {
+        Ok(Self {
+            inner,
+            sealer: StreamSealer::new(key)?,
+            out: Vec::new(),
+            written: 0,
+            finished: false,
+        })
+    }
+
+    /// Sets the number of plaintext bytes in each chunk, between 1 byte and 16 MiB. Has no effect
+    /// once data was written
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.sealer.set_chunk_size(chunk_size);
+        self
+    }
+
+    /// Returns the inner writer, call this after shutting the writer down
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes the sealed chunk that is not written yet
+    fn poll_write_out(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.out.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.out[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.out.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncEncryptWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.finished {
+            return Poll::Ready(Err(io::Error::other("stream is already finished")));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            ready!(this.poll_write_out(cx))?;
+            if !this.sealer.is_full() {
+                return Poll::Ready(Ok(this.sealer.buffer(buf)));
+            }
+            this.out = this.sealer.seal(false)?;
+        }
+    }
+
+    /// Flushes the inner writer. Buffered plaintext is only written once a chunk is full or the
+    /// stream is shut down
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_out(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    /// Writes the last chunk and shuts down the inner writer
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_out(cx))?;
+        if !this.finished {
+            this.out = this.sealer.seal(true)?;
+            this.finished = true;
+            ready!(this.poll_write_out(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// Async version of [`DecryptReader`](crate::DecryptReader) for tokio, reading streams written by
+/// either writer.
+///
+/// Errors are returned as [`io::ErrorKind::InvalidData`] holding a [`DecryptError`]. A stream
+/// that was cut off fails on its last chunk, so all data must be read before trusting any of it.
+pub struct AsyncDecryptReader<R: AsyncRead + Unpin> {
+    inner: R,
+    opener: StreamOpener,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecryptReader<R> {
+    /// Reads the header from `inner` and derives the key from `password` on the blocking thread
+    /// pool, or unwraps it from the first key slot `password` unlocks
+    pub async fn new(mut inner: R, password: &str) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner).await?;
+        let key = DerivedKey::unlock_async(&header, password).await?;
+        Self::from_parts(inner, key, header, chunk_size)
+    }
+
+    /// Reads the header from `inner` and unwraps the key from the recipient key slot of
+    /// `secret_key`
+    pub async fn with_secret_key(
+        mut inner: R,
+        secret_key: &SecretKey,
+    ) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner).await?;
+        let key = DerivedKey::unlock_with_secret_key(&header, secret_key)?;
+        Self::from_parts(inner, key, header, chunk_size)
+    }
+
+    /// Reads the header from `inner` and decrypts with an already derived `key`
+    pub async fn with_key(mut inner: R, key: &DerivedKey) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner).await?;
+        if !key.matches(&header) {
+            return Err(DecryptError::KeyMismatch);
+        }
+        Self::from_parts(inner, key.clone(), header, chunk_size)
+    }
+
+    /// Returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn from_parts(
+        inner: R,
+        key: DerivedKey,
+        header: Encrypted,
+        chunk_size: u32,
+    ) -> Result<Self, DecryptError> {
+        Ok(Self {
+            inner,
+            opener: StreamOpener::new(key, header, chunk_size)?,
+        })
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecryptReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let mut read_buf = [0u8; READ_BUFFER_LEN];
+        loop {
+            if let Some(result) = this.opener.read(buf.initialize_unfilled()) {
+                buf.advance(result?);
+                return Poll::Ready(Ok(()));
+            }
+            let want = this.opener.wanted().min(read_buf.len());
+            let mut read_buf = ReadBuf::new(&mut read_buf[..want]);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+            this.opener.fill(read_buf.filled());
+        }
+    }
+}
+
+/// Reads the header of a stream from `inner`
+async fn read_header<R: AsyncRead + Unpin>(
+    inner: &mut R,
+) -> Result<(Encrypted, u32), DecryptError> {
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        if let Some(header) = parse_header(&bytes)? {
+            return Ok(header);
+        }
+        match inner.read(&mut buf).await.map_err(DecryptError::Io)? {
+            0 => return Err(DecryptError::Truncated),
+            n => bytes.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        AsyncDecryptReader, AsyncEncryptWriter, BytesEncrypter, Config, DecryptError,
+        DecryptReader, DerivedKey, EncryptWriter, Encryptable, KdfParams, SecretBytes,
+    };
+
+    #[tokio::test]
+    async fn round_trip() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let key = DerivedKey::new_async("password", &config).await.unwrap();
+        let data: Vec<u8> = (0..1_000).map(|i| i as u8).collect();
+
+        for len in [0, 1, 100, 101, 1_000] {
+            let mut writer = AsyncEncryptWriter::with_key(Vec::new(), &key)
+                .unwrap()
+                .chunk_size(100);
+            for part in data[..len].chunks(7) {
+                writer.write_all(part).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+            let stream = writer.into_inner();
+
+            // Both readers read what either writer wrote
+            let mut out = Vec::new();
+            DecryptReader::with_key(&stream[..], &key)
+                .unwrap()
+                .read_to_end(&mut out)
+                .unwrap();
+            assert_eq!(out, &data[..len]);
+
+            let mut sync_writer = EncryptWriter::with_key(Vec::new(), &key)
+                .unwrap()
+                .chunk_size(100);
+            std::io::Write::write_all(&mut sync_writer, &data[..len]).unwrap();
+            for stream in [stream, sync_writer.finish().unwrap()] {
+                let mut out = Vec::new();
+                AsyncDecryptReader::new(&stream[..], "password")
+                    .await
+                    .unwrap()
+                    .read_to_end(&mut out)
+                    .await
+                    .unwrap();
+                assert_eq!(out, &data[..len]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let mut writer = AsyncEncryptWriter::new(Vec::new(), "password", &config)
+            .await
+            .unwrap()
+            .chunk_size(100);
+        writer.write_all(&[1; 1_000]).await.unwrap();
+        writer.shutdown().await.unwrap();
+        let stream = writer.into_inner();
+
+        let mut reader = AsyncDecryptReader::new(&stream[..stream.len() - 116], "password")
+            .await
+            .unwrap();
+        let e = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+        assert!(matches!(
+            *e.into_inner().unwrap().downcast::<DecryptError>().unwrap(),
+            DecryptError::Truncated
+        ));
+    }
+
+    #[tokio::test]
+    async fn encryptable() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted =
+            BytesEncrypter::encrypt_with_async(&SecretBytes::from(b"test"), "password", &config)
+                .await
+                .unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt_async(&encrypted, "password")
+                .await
+                .unwrap(),
+            b"test"
+        );
+        assert!(matches!(
+            BytesEncrypter::decrypt_async(&encrypted, "wrong").await,
+            Err(DecryptError::WrongPassword)
+        ));
+    }
+}
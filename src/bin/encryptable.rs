@@ -0,0 +1,254 @@
+//! Encrypts and decrypts files with `BytesEncrypter`
+
+use std::{
+    fs,
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use encryptable::{
+    BytesEncrypter, Cipher, Config, DecryptError, Encryptable, Encrypted, SecretBytes,
+};
+use zeroize::Zeroizing;
+
+/// Exit code for a wrong passphrase
+const EXIT_AUTHENTICATION: u8 = 3;
+/// Exit code for input that is not valid encrypted data, or was changed or cut off
+const EXIT_MALFORMED: u8 = 4;
+/// Exit code for any other error
+const EXIT_FAILURE: u8 = 1;
+/// Times a passphrase is prompted for before giving up
+const PROMPT_ATTEMPTS: usize = 3;
+
+#[derive(Parser)]
+#[command(version, about)]
+#[command(after_help = "Exit codes: 0 success, 1 error, 2 invalid arguments, \
+3 wrong passphrase, 4 corrupt, tampered or truncated input")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encrypts a file
+    Encrypt {
+        #[command(flatten)]
+        io: IoArgs,
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+        /// Writes ASCII armored text instead of binary
+        #[arg(short, long)]
+        armor: bool,
+        /// Cipher to encrypt with
+        #[arg(short, long, value_enum, default_value_t = CipherArg::Xchacha20poly1305)]
+        cipher: CipherArg,
+    },
+    /// Decrypts a file, binary or ASCII armored
+    Decrypt {
+        #[command(flatten)]
+        io: IoArgs,
+        #[command(flatten)]
+        passphrase: PassphraseArgs,
+    },
+}
+
+#[derive(Args)]
+struct IoArgs {
+    /// File to read, stdin if missing or `-`
+    input: Option<PathBuf>,
+    /// File to write, stdout if missing or `-`
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+#[group(multiple = false)]
+struct PassphraseArgs {
+    /// Reads the passphrase from the first line of a file instead of prompting
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+    /// Reads the passphrase from an environment variable instead of prompting
+    #[arg(long, value_name = "VAR")]
+    passphrase_env: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum CipherArg {
+    Fernet,
+    Aes256gcm,
+    Chacha20poly1305,
+    Xchacha20poly1305,
+}
+
+impl From<CipherArg> for Cipher {
+    fn from(cipher: CipherArg) -> Self {
+        match cipher {
+            CipherArg::Fernet => Cipher::Fernet,
+            CipherArg::Aes256gcm => Cipher::Aes256Gcm,
+            CipherArg::Chacha20poly1305 => Cipher::ChaCha20Poly1305,
+            CipherArg::Xchacha20poly1305 => Cipher::XChaCha20Poly1305,
+        }
+    }
+}
+
+/// Error of a command, mapped to an exit code
+enum Error {
+    Decrypt(DecryptError),
+    Other(String),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Other(e.to_string())
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Decrypt(e)) => {
+            eprintln!("error: {e}");
+            match e {
+                DecryptError::WrongPassword | DecryptError::Authentication => {
+                    ExitCode::from(EXIT_AUTHENTICATION)
+                }
+                DecryptError::Tampered
+                | DecryptError::Truncated
+                | DecryptError::Malformed(_)
+                | DecryptError::UnsupportedVersion(_)
+                | DecryptError::UnsupportedCipher(_)
+                | DecryptError::UnsupportedKdf(_)
+                | DecryptError::UnsupportedCompression(_)
+                | DecryptError::UnsupportedPadding(_)
+                | DecryptError::TooLarge => ExitCode::from(EXIT_MALFORMED),
+                _ => ExitCode::from(EXIT_FAILURE),
+            }
+        }
+        Err(Error::Other(e)) => {
+            eprintln!("error: {e}");
+            ExitCode::from(EXIT_FAILURE)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    match cli.command {
+        Command::Encrypt {
+            io,
+            passphrase,
+            armor,
+            cipher,
+        } => {
+            let data = SecretBytes::new(read_input(&io.input)?);
+            let passphrase = passphrase.get(true)?;
+            let config = Config::new().cipher(cipher.into());
+            let encrypted = BytesEncrypter::encrypt_with(&data, &passphrase, &config)
+                .map_err(|e| Error::Other(e.to_string()))?;
+
+            let out = match armor {
+                true => encrypted
+                    .to_armored()
+                    .map_err(|e| Error::Other(e.to_string()))?
+                    .into_bytes(),
+                false => encrypted
+                    .to_bytes()
+                    .map_err(|e| Error::Other(e.to_string()))?,
+            };
+            write_output(&io.output, &out)
+        }
+        Command::Decrypt { io, passphrase } => {
+            let input = read_input(&io.input)?;
+            let encrypted = decode(&input).map_err(Error::Decrypt)?;
+            let mut attempts = 1;
+            let data = loop {
+                match BytesEncrypter::decrypt(&encrypted, &passphrase.get(false)?) {
+                    Err(DecryptError::WrongPassword)
+                        if passphrase.prompts() && attempts < PROMPT_ATTEMPTS =>
+                    {
+                        eprintln!("Wrong passphrase, try again");
+                        attempts += 1;
+                    }
+                    result => break result.map_err(Error::Decrypt)?,
+                }
+            };
+            write_output(&io.output, &data)
+        }
+    }
+}
+
+impl PassphraseArgs {
+    /// Whether the passphrase is prompted for, so it can be asked for again
+    fn prompts(&self) -> bool {
+        self.passphrase_file.is_none() && self.passphrase_env.is_none()
+    }
+
+    /// Reads the passphrase from the file or variable, or prompts for it without echo. A new
+    /// passphrase is prompted for twice
+    fn get(&self, confirm: bool) -> Result<Zeroizing<String>, Error> {
+        if let Some(path) = &self.passphrase_file {
+            let contents = Zeroizing::new(fs::read_to_string(path)?);
+            let line = contents.lines().next().unwrap_or_default();
+            return Ok(Zeroizing::new(line.to_string()));
+        }
+        if let Some(var) = &self.passphrase_env {
+            return std::env::var(var)
+                .map(Zeroizing::new)
+                .map_err(|e| Error::Other(format!("{var}: {e}")));
+        }
+
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Passphrase: ")?);
+        if confirm {
+            let again = Zeroizing::new(rpassword::prompt_password("Confirm passphrase: ")?);
+            if passphrase != again {
+                return Err(Error::Other("passphrases do not match".to_string()));
+            }
+        }
+        Ok(passphrase)
+    }
+}
+
+/// The file `path` names, `None` for stdin or stdout
+fn file(path: &Option<PathBuf>) -> Option<&Path> {
+    path.as_deref().filter(|path| path.as_os_str() != "-")
+}
+
+fn read_input(path: &Option<PathBuf>) -> Result<Vec<u8>, Error> {
+    match file(path) {
+        Some(path) => Ok(fs::read(path)?),
+        None => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input)?;
+            Ok(input)
+        }
+    }
+}
+
+fn write_output(path: &Option<PathBuf>, data: &[u8]) -> Result<(), Error> {
+    match file(path) {
+        Some(path) => Ok(fs::write(path, data)?),
+        None => {
+            let mut stdout = io::stdout().lock();
+            if stdout.is_terminal() && std::str::from_utf8(data).is_err() {
+                return Err(Error::Other(
+                    "refusing to write binary data to a terminal, use --output or --armor"
+                        .to_string(),
+                ));
+            }
+            stdout.write_all(data)?;
+            Ok(stdout.flush()?)
+        }
+    }
+}
+
+/// Decodes binary or ASCII armored input
+fn decode(input: &[u8]) -> Result<Encrypted, DecryptError> {
+    if input.starts_with(b"ENCR") {
+        return Encrypted::from_bytes(input);
+    }
+    std::str::from_utf8(input)
+        .map_err(|_| DecryptError::Malformed("input is not binary or armored"))?
+        .parse()
+}
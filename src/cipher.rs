@@ -0,0 +1,252 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm,
+};
+use base64::{engine::general_purpose, Engine};
+#[cfg(feature = "bincode")]
+use bincode::{Decode, Encode};
+use chacha20poly1305::{ChaCha20Poly1305, XChaCha20Poly1305};
+use fernet::Fernet;
+use rand::{thread_rng, RngCore};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{BoxError, DecryptError, SecretBytes};
+
+/// Fernet token version byte
+const FERNET_VERSION: u8 = 0x80;
+/// version + timestamp + iv + one AES block + hmac
+const FERNET_MIN_LEN: usize = 1 + 8 + 16 + 16 + 32;
+
+/// Cipher used to encrypt the data. The id of the cipher is stored in the `Encrypted`, so
+/// decryption always uses the cipher the data was encrypted with.
+///
+/// The AEAD ciphers also authenticate the header of the `Encrypted`, Fernet does not.
+///
+/// [`Cipher::Age`] writes an [age v1](https://age-encryption.org/v1) file instead of the binary
+/// format, so the data can be exchanged with the `age` CLI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+#[non_exhaustive]
+pub enum Cipher {
+    /// Fernet, AES-128-CBC with HMAC Sha256
+    Fernet,
+    /// AES-256-GCM with a random 96 bit nonce
+    Aes256Gcm,
+    /// ChaCha20-Poly1305 with a random 96 bit nonce
+    ChaCha20Poly1305,
+    /// XChaCha20-Poly1305 with a random 192 bit nonce
+    #[default]
+    XChaCha20Poly1305,
+    /// An [age v1](https://age-encryption.org/v1) file, read and written by the `age` CLI and
+    /// other age implementations. Requires the `age` feature.
+    ///
+    /// [`Encrypted::to_bytes`](crate::Encrypted::to_bytes) writes the age file and
+    /// [`Encrypted::to_armored`](crate::Encrypted::to_armored) its ASCII armor, and both are read
+    /// back by [`Encrypted::from_bytes`](crate::Encrypted::from_bytes) and [`str::parse`].
+    /// Encrypt with a password and `KdfParams::scrypt(log_n, 8, 1)`, the age CLI uses a
+    /// `log_n` of 18, or to the [`PublicKey`](crate::PublicKey)s of recipients, which are written
+    /// as `age1...` strings.
+    ///
+    /// The age format has no header fields, so associated data, type tags, expiry times,
+    /// compression and padding are not supported, and neither are key slots or streams.
+    ///
+    /// ```
+    /// # #[cfg(feature = "age")] {
+    /// use encryptable::{BytesEncrypter, Cipher, Config, Encryptable, Encrypted, KdfParams, SecretBytes};
+    ///
+    /// let config = Config::new().cipher(Cipher::Age).kdf(KdfParams::scrypt(10, 8, 1));
+    /// let encrypted =
+    ///     BytesEncrypter::encrypt_with(&SecretBytes::from("test"), "password", &config).unwrap();
+    /// assert!(encrypted.to_bytes().unwrap().starts_with(b"age-encryption.org/v1\n"));
+    ///
+    /// let armored = encrypted.to_armored().unwrap();
+    /// assert!(armored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----\n"));
+    /// let encrypted: Encrypted = armored.parse().unwrap();
+    /// assert_eq!(&*BytesEncrypter::decrypt(&encrypted, "password").unwrap(), b"test");
+    /// # }
+    /// ```
+    Age,
+}
+
+impl Cipher {
+    /// Id of the cipher in the binary format
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::Fernet => 1,
+            Self::Aes256Gcm => 2,
+            Self::ChaCha20Poly1305 => 3,
+            Self::XChaCha20Poly1305 => 4,
+            // Only in the associated data of age files, which never use the binary format
+            Self::Age => 5,
+        }
+    }
+
+    /// Cipher for an id in the binary format
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Fernet),
+            2 => Some(Self::Aes256Gcm),
+            3 => Some(Self::ChaCha20Poly1305),
+            4 => Some(Self::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    /// Length of the key in bytes, the age file key is 16 bytes and the other ciphers use 32
+    pub(crate) fn key_len(self) -> usize {
+        match self {
+            Self::Age => 16,
+            _ => 32,
+        }
+    }
+
+    /// Length of the nonce stored in the `Encrypted`. Fernet keeps its IV inside the token and
+    /// age its nonce inside the payload
+    pub(crate) fn nonce_len(self) -> usize {
+        match self {
+            Self::Fernet | Self::Age => 0,
+            Self::Aes256Gcm | Self::ChaCha20Poly1305 => 12,
+            Self::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Generates a random nonce of `nonce_len` bytes
+    pub(crate) fn generate_nonce(self) -> Vec<u8> {
+        let mut nonce = vec![0u8; self.nonce_len()];
+        thread_rng().fill_bytes(&mut nonce);
+        nonce
+    }
+
+    /// Encrypts `plaintext`, authenticating `aad` along with it. Fails if `aad` is not empty for
+    /// Fernet, which would silently leave it unauthenticated
+    pub(crate) fn encrypt(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, BoxError> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let result = match self {
+            Self::Fernet if !aad.is_empty() => {
+                return Err("fernet does not support associated data".into())
+            }
+            Self::Fernet => {
+                let token = fernet(key)?.encrypt(plaintext);
+                return Ok(general_purpose::URL_SAFE.decode(token)?);
+            }
+            Self::Aes256Gcm => Aes256Gcm::new_from_slice(key)?.encrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new_from_slice(key)?.encrypt(nonce.into(), payload)
+            }
+            Self::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new_from_slice(key)?.encrypt(nonce.into(), payload)
+            }
+            // Encrypted by the age module, this is only reached without the `age` feature
+            Self::Age => return Err("age requires the age feature".into()),
+        };
+        result.map_err(|_| "encryption failed".into())
+    }
+
+    /// Decrypts `ciphertext`, checking it against `aad`. Fernet can not check associated data, so
+    /// it fails if `aad` is not empty
+    pub(crate) fn decrypt(
+        self,
+        key: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<SecretBytes, DecryptError> {
+        if nonce.len() != self.nonce_len() {
+            return Err(DecryptError::Malformed("nonce has the wrong length"));
+        }
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let result = match self {
+            Self::Fernet if !aad.is_empty() => {
+                return Err(DecryptError::Malformed(
+                    "fernet does not support associated data",
+                ))
+            }
+            Self::Fernet => {
+                // Tokens are stored decoded, older data holds the base64 text. A decoded token
+                // starts with the version byte, which is not a base64 character
+                let token = match ciphertext.first() {
+                    Some(&FERNET_VERSION) => general_purpose::URL_SAFE.encode(ciphertext),
+                    _ => std::str::from_utf8(ciphertext)
+                        .map_err(|_| DecryptError::Malformed("token is not valid utf-8"))?
+                        .to_string(),
+                };
+                check_fernet_token(&token)?;
+                let f = fernet(key).map_err(DecryptError::Kdf)?;
+                return f
+                    .decrypt(&token)
+                    .map(SecretBytes::new)
+                    .map_err(|_| DecryptError::Authentication);
+            }
+            Self::Aes256Gcm => Aes256Gcm::new_from_slice(key)
+                .map_err(|e| DecryptError::Kdf(e.into()))?
+                .decrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305 => ChaCha20Poly1305::new_from_slice(key)
+                .map_err(|e| DecryptError::Kdf(e.into()))?
+                .decrypt(nonce.into(), payload),
+            Self::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(key)
+                .map_err(|e| DecryptError::Kdf(e.into()))?
+                .decrypt(nonce.into(), payload),
+            Self::Age => return Err(DecryptError::UnsupportedCipher(self.id())),
+        };
+        result
+            .map(SecretBytes::new)
+            .map_err(|_| DecryptError::Authentication)
+    }
+}
+
+/// Creation time stored in a Fernet token, in seconds since the unix epoch
+pub(crate) fn fernet_timestamp(ciphertext: &[u8]) -> Option<u64> {
+    let decoded;
+    let token = match ciphertext.first() {
+        Some(&FERNET_VERSION) => ciphertext,
+        _ => {
+            decoded = general_purpose::URL_SAFE.decode(ciphertext).ok()?;
+            &decoded
+        }
+    };
+    match token.first() {
+        Some(&FERNET_VERSION) => Some(u64::from_be_bytes(token.get(1..9)?.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Creates a Fernet from a raw 32 byte key
+fn fernet(key: &[u8]) -> Result<Fernet, BoxError> {
+    let key = Zeroizing::new(general_purpose::URL_SAFE.encode(key));
+    Fernet::new(&key).ok_or_else(|| "fernet needs a 32 byte key".into())
+}
+
+/// Checks the structure of a Fernet token so a malformed token is not reported as a wrong password
+fn check_fernet_token(token: &str) -> Result<(), DecryptError> {
+    let raw = general_purpose::URL_SAFE
+        .decode(token)
+        .map_err(|_| DecryptError::Malformed("token is not valid base64"))?;
+
+    match raw.first() {
+        Some(&FERNET_VERSION) => {}
+        Some(&v) => return Err(DecryptError::UnsupportedVersion(v)),
+        None => return Err(DecryptError::Malformed("token is empty")),
+    }
+
+    if raw.len() < FERNET_MIN_LEN || !(raw.len() - FERNET_MIN_LEN).is_multiple_of(16) {
+        return Err(DecryptError::Malformed("token has an invalid length"));
+    }
+
+    Ok(())
+}
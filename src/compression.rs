@@ -0,0 +1,173 @@
+#[cfg(any(feature = "zstd", feature = "deflate"))]
+use std::io::Read;
+
+#[cfg(feature = "bincode")]
+use bincode::{Decode, Encode};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{BoxError, DecryptError, SecretBytes};
+
+/// Default of [`Encryptable::max_decompressed_len`](crate::Encryptable::max_decompressed_len)
+pub(crate) const DEFAULT_MAX_DECOMPRESSED_LEN: usize = 64 * 1024 * 1024;
+/// Bytes decompressed at a time
+#[cfg(any(feature = "zstd", feature = "deflate"))]
+const DECOMPRESS_CHUNK_LEN: usize = 16 * 1024;
+
+/// Compression applied to the plaintext before it is encrypted, set with
+/// [`Config::compression`](crate::Config::compression). The id of the algorithm is stored in the
+/// `Encrypted` and the data is decompressed when it is decrypted.
+///
+/// Each algorithm needs the feature of the same name, to compress and to decompress. The
+/// plaintext is stored uncompressed if compressing does not make it smaller.
+///
+/// The length of compressed data depends on its contents, so do not compress secrets together
+/// with data an attacker controls if they can see the length of the result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+#[non_exhaustive]
+pub enum Compression {
+    /// Zstandard at level 3, requires the `zstd` feature
+    Zstd,
+    /// Deflate at level 6, requires the `deflate` feature
+    Deflate,
+}
+
+impl Compression {
+    /// Id of the algorithm in the binary format
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::Zstd => 1,
+            Self::Deflate => 2,
+        }
+    }
+
+    /// Algorithm for an id in the binary format
+    pub(crate) fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Self::Zstd),
+            2 => Some(Self::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Compresses `data`
+    #[cfg_attr(
+        not(all(feature = "zstd", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn compress(self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, BoxError> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(Zeroizing::new(zstd::bulk::compress(data, 3)?)),
+            #[cfg(feature = "deflate")]
+            Self::Deflate => {
+                use std::io::Write;
+
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(Zeroizing::new(encoder.finish()?))
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(format!("{self:?} compression is not enabled").into()),
+        }
+    }
+
+    /// Decompresses `data`, failing with [`DecryptError::TooLarge`] if it is longer than
+    /// `max_len`
+    #[cfg_attr(
+        not(all(feature = "zstd", feature = "deflate")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn decompress(
+        self,
+        data: &[u8],
+        max_len: usize,
+    ) -> Result<SecretBytes, DecryptError> {
+        match self {
+            #[cfg(feature = "zstd")]
+            Self::Zstd => {
+                let decoder = zstd::stream::read::Decoder::new(data).map_err(invalid)?;
+                read_limited(decoder, max_len)
+            }
+            #[cfg(feature = "deflate")]
+            Self::Deflate => read_limited(flate2::read::DeflateDecoder::new(data), max_len),
+            #[allow(unreachable_patterns)]
+            _ => Err(DecryptError::UnsupportedCompression(self.id())),
+        }
+    }
+}
+
+/// Reads all of `decoder`, stopping one byte past `max_len` so a small input that decompresses to
+/// a huge output can not exhaust memory. The output is grown by hand, copying it into a bigger
+/// buffer and wiping the old one, so reallocating does not leave copies of the plaintext behind
+#[cfg(any(feature = "zstd", feature = "deflate"))]
+fn read_limited(mut decoder: impl Read, max_len: usize) -> Result<SecretBytes, DecryptError> {
+    let limit = max_len.saturating_add(1);
+    let mut out = Zeroizing::new(Vec::with_capacity(DECOMPRESS_CHUNK_LEN.min(limit)));
+    let mut chunk = Zeroizing::new([0u8; DECOMPRESS_CHUNK_LEN]);
+
+    while out.len() < limit {
+        let wanted = DECOMPRESS_CHUNK_LEN.min(limit - out.len());
+        let n = match decoder.read(&mut chunk[..wanted]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(invalid(e)),
+        };
+        if out.capacity() - out.len() < n {
+            let capacity = out.capacity().saturating_mul(2).clamp(out.len() + n, limit);
+            let mut grown = Zeroizing::new(Vec::with_capacity(capacity));
+            grown.extend_from_slice(&out);
+            out = grown;
+        }
+        out.extend_from_slice(&chunk[..n]);
+    }
+
+    match out.len() > max_len {
+        true => Err(DecryptError::TooLarge),
+        false => Ok(SecretBytes::new(std::mem::take(&mut *out))),
+    }
+}
+
+#[cfg(any(feature = "zstd", feature = "deflate"))]
+fn invalid(_: std::io::Error) -> DecryptError {
+    DecryptError::Malformed("compressed data is invalid")
+}
+
+#[cfg(all(test, feature = "zstd", feature = "deflate"))]
+mod tests {
+    use crate::{Compression, DecryptError};
+
+    #[test]
+    fn compression() {
+        let data =
+            br#"{"name":"name","tags":["a","a","a","a","a","a","a","a","a","a"]}"#.repeat(100);
+        for compression in [Compression::Zstd, Compression::Deflate] {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(compressed.len() < data.len() / 5);
+            assert_eq!(
+                &*compression.decompress(&compressed, data.len()).unwrap(),
+                &data[..]
+            );
+
+            // Stops at the limit instead of decompressing everything
+            let bomb = compression.compress(&vec![0u8; 10_000_000]).unwrap();
+            assert_eq!(
+                compression.decompress(&bomb, 10_000_000).unwrap().len(),
+                10_000_000
+            );
+            assert!(matches!(
+                compression.decompress(&bomb, 1_000),
+                Err(DecryptError::TooLarge)
+            ));
+            assert!(matches!(
+                compression.decompress(b"not compressed", 1_000),
+                Err(DecryptError::Malformed(_))
+            ));
+        }
+    }
+}
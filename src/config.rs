@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::{Cipher, Compression, KdfParams, Padding};
+
+/// Settings used when encrypting. Decryption does not need a `Config`, everything it needs is
+/// read back from the `Encrypted`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    pub(crate) cipher: Cipher,
+    pub(crate) kdf: KdfParams,
+    pub(crate) expires_after: Option<Duration>,
+    pub(crate) compression: Option<Compression>,
+    pub(crate) padding: Option<Padding>,
+}
+
+impl Config {
+    /// Creates the default config
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the cipher used to encrypt the data
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Sets the parameters used to derive the key from the password
+    pub fn kdf(mut self, kdf: KdfParams) -> Self {
+        self.kdf = kdf;
+        self
+    }
+
+    /// Stores an expiry time `duration` after encryption, decrypting fails with
+    /// [`DecryptError::Expired`](crate::DecryptError::Expired) once it has passed. Not supported
+    /// by [`Cipher::Fernet`] or [`Cipher::Age`], see [`Encryptable::decrypt_with_ttl`](crate::Encryptable::decrypt_with_ttl)
+    /// for a limit set when decrypting instead
+    pub fn expires_after(mut self, duration: Duration) -> Self {
+        self.expires_after = Some(duration);
+        self
+    }
+
+    /// Compresses the plaintext before encrypting it. Not supported by [`Cipher::Fernet`],
+    /// [`Cipher::Age`] or streams
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Pads the plaintext before encrypting it, so the ciphertext does not reveal its exact
+    /// length. Not supported by [`Cipher::Fernet`], [`Cipher::Age`] or streams
+    pub fn padding(mut self, padding: Padding) -> Self {
+        self.padding = Some(padding);
+        self
+    }
+}
@@ -0,0 +1,1075 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(any(feature = "serde", feature = "bincode"))]
+use base64::{engine::general_purpose, Engine};
+#[cfg(feature = "bincode")]
+use bincode::{
+    de::Decoder,
+    enc::Encoder,
+    error::{DecodeError, EncodeError},
+    Decode, Encode,
+};
+#[cfg(feature = "serde")]
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    ser::{self, SerializeStruct},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{
+    cipher::fernet_timestamp, Cipher, Compression, DecryptError, EncryptError, KdfAlgorithm,
+    KdfParams, KeySlot, Padding, SecretBytes,
+};
+
+/// Magic bytes at the start of the binary format
+const MAGIC: &[u8; 4] = b"ENCR";
+/// Current version of the binary format
+const VERSION: u8 = 1;
+/// Marks the end of the header fields
+const END_OF_FIELDS: u8 = 0;
+/// Header fields with this bit set in their tag must be understood by the reader
+const CRITICAL_FIELD: u8 = 0x80;
+/// Header field holding the key slots
+const KEY_SLOTS_FIELD: u8 = CRITICAL_FIELD | 1;
+/// Header field marking a stream, holding its chunk size
+const STREAM_FIELD: u8 = CRITICAL_FIELD | 2;
+/// Header field holding the type tag
+const TYPE_TAG_FIELD: u8 = CRITICAL_FIELD | 3;
+/// Header field holding the creation time
+const CREATED_AT_FIELD: u8 = CRITICAL_FIELD | 4;
+/// Header field holding the expiry time
+const EXPIRES_AT_FIELD: u8 = CRITICAL_FIELD | 5;
+/// Header field holding the compression id
+const COMPRESSION_FIELD: u8 = CRITICAL_FIELD | 6;
+/// Header field holding the padding scheme
+const PADDING_FIELD: u8 = CRITICAL_FIELD | 7;
+/// Header field holding the key check
+const KEY_CHECK_FIELD: u8 = 4;
+/// Written in place of the 16 byte salt of 0.1 by binary serde formats and bincode, followed by
+/// the binary format instead of the Fernet token
+#[cfg(any(feature = "serde", feature = "bincode"))]
+const BINARY_MARKER: [u8; 16] = *b"encryptable ENCR";
+/// How far in the future the creation time may be, to allow for clocks that are slightly off
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(60);
+
+/// Represents the encrypted form of a `T`. Contains the algorithms used, the salt and the data.
+///
+/// `T` is the type that was encrypted, so an `Encrypted<User>` can only be decrypted by an
+/// [`Encryptable<User>`](crate::Encryptable) implementation. It only exists at compile time and
+/// defaults to `SecretBytes`, use [`Encrypted::cast`] to change it. Implementations can also
+/// store a [type tag](crate::Encryptable::type_tag) in the data, which is checked when decrypting.
+///
+/// Use [`Encrypted::to_bytes`] and [`Encrypted::from_bytes`] to store it in a versioned binary format,
+/// or `Display` and [`str::parse`] for an ASCII armored string that can be pasted into a config
+/// file.
+/// With the `serde` feature it is a struct with base64 strings for the salt, nonce and data in
+/// human readable formats, and the binary format of [`Encrypted::to_bytes`] in binary ones.
+pub struct Encrypted<T = SecretBytes> {
+    pub(crate) cipher: Cipher,
+    pub(crate) kdf: KdfParams,
+    pub(crate) salt: Vec<u8>,
+    pub(crate) nonce: Vec<u8>,
+    pub(crate) data: Vec<u8>,
+    pub(crate) slots: Vec<KeySlot>,
+    pub(crate) type_tag: Option<String>,
+    /// Commits to the key so a wrong password can be told apart from tampered data, empty in
+    /// data encrypted before it was added
+    pub(crate) key_check: Vec<u8>,
+    /// Seconds since the unix epoch, Fernet tokens hold their own creation time instead
+    pub(crate) created_at: Option<u64>,
+    /// Seconds since the unix epoch
+    pub(crate) expires_at: Option<u64>,
+    /// Compression of the plaintext, reversed after decrypting
+    pub(crate) compression: Option<Compression>,
+    /// Padding of the plaintext, removed after decrypting and before decompressing
+    pub(crate) padding: Option<Padding>,
+    pub(crate) _type: PhantomData<fn() -> T>,
+}
+
+// Implemented by hand so `T` does not need to implement them
+
+impl<T> fmt::Debug for Encrypted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encrypted")
+            .field("cipher", &self.cipher)
+            .field("kdf", &self.kdf)
+            .field("salt", &self.salt)
+            .field("nonce", &self.nonce)
+            .field("data", &self.data)
+            .field("slots", &self.slots)
+            .field("type_tag", &self.type_tag)
+            .field("key_check", &self.key_check)
+            .field("created_at", &self.created_at)
+            .field("expires_at", &self.expires_at)
+            .field("compression", &self.compression)
+            .field("padding", &self.padding)
+            .finish()
+    }
+}
+
+impl<T> Clone for Encrypted<T> {
+    fn clone(&self) -> Self {
+        Self {
+            cipher: self.cipher,
+            kdf: self.kdf,
+            salt: self.salt.clone(),
+            nonce: self.nonce.clone(),
+            data: self.data.clone(),
+            slots: self.slots.clone(),
+            type_tag: self.type_tag.clone(),
+            key_check: self.key_check.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            compression: self.compression,
+            padding: self.padding,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Encrypted<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cipher == other.cipher
+            && self.kdf == other.kdf
+            && self.salt == other.salt
+            && self.nonce == other.nonce
+            && self.data == other.data
+            && self.slots == other.slots
+            && self.type_tag == other.type_tag
+            && self.key_check == other.key_check
+            && self.created_at == other.created_at
+            && self.expires_at == other.expires_at
+            && self.compression == other.compression
+            && self.padding == other.padding
+    }
+}
+
+impl<T> Eq for Encrypted<T> {}
+
+/// bincode stores [`BINARY_MARKER`] and the binary format of [`Encrypted::to_bytes`] as a byte
+/// vector, so fields added to the format later do not change the layout. This has the same shape
+/// as the salt and the Fernet token written by 0.1, which can still be decoded
+#[cfg(feature = "bincode")]
+impl<T> Encode for Encrypted<T> {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        BINARY_MARKER.encode(encoder)?;
+        self.to_bytes()
+            .map_err(|e| EncodeError::OtherString(e.to_string()))?
+            .encode(encoder)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<T, Context> Decode<Context> for Encrypted<T> {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let salt = <[u8; 16]>::decode(decoder)?;
+        let data = Vec::<u8>::decode(decoder)?;
+        Self::from_binary_fields(salt, &data).map_err(|e| DecodeError::OtherString(e.to_string()))
+    }
+}
+
+#[cfg(feature = "bincode")]
+bincode::impl_borrow_decode!(Encrypted<T>, T);
+
+/// Fields of [`Encrypted`] in human readable serde formats
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Encrypted", bound = "")]
+struct Fields<T> {
+    /// Data written before the cipher and kdf were stored only has a salt and a Fernet token
+    #[serde(default = "legacy_cipher")]
+    cipher: Cipher,
+    #[serde(default = "legacy_kdf")]
+    kdf: KdfParams,
+    #[serde(with = "crate::bytes")]
+    salt: Vec<u8>,
+    #[serde(default, with = "crate::bytes")]
+    nonce: Vec<u8>,
+    #[serde(with = "crate::bytes")]
+    data: Vec<u8>,
+    #[serde(default)]
+    slots: Vec<KeySlot>,
+    #[serde(default)]
+    type_tag: Option<String>,
+    #[serde(default, with = "crate::bytes")]
+    key_check: Vec<u8>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    compression: Option<Compression>,
+    #[serde(default)]
+    padding: Option<Padding>,
+    #[serde(skip)]
+    _type: PhantomData<fn() -> T>,
+}
+
+/// Binary serde formats store [`BINARY_MARKER`] as the salt and the binary format of
+/// [`Encrypted::to_bytes`] as the data, which has the same shape as the salt and the Fernet token
+/// written by 0.1
+#[cfg(feature = "serde")]
+impl<T> Serialize for Encrypted<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            return Fields::serialize(self, serializer);
+        }
+        let bytes = self.to_bytes().map_err(ser::Error::custom)?;
+        let mut fields = serializer.serialize_struct("Encrypted", 2)?;
+        fields.serialize_field("salt", &BINARY_MARKER)?;
+        fields.serialize_field("data", &Bytes(&bytes))?;
+        fields.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T> Deserialize<'de> for Encrypted<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let encrypted: Self = Fields::deserialize(deserializer)?;
+            // So `to_bytes` can not fail on it
+            let header = encrypted.header().map_err(de::Error::custom)?;
+            // The fields of an age file are read from its header
+            if encrypted.cipher == Cipher::Age {
+                return Self::from_bytes(&[header, encrypted.data].concat())
+                    .map_err(de::Error::custom);
+            }
+            return Ok(encrypted);
+        }
+        deserializer.deserialize_struct("Encrypted", &["salt", "data"], BinaryVisitor(PhantomData))
+    }
+}
+
+#[cfg(feature = "serde")]
+struct Bytes<'a>(&'a [u8]);
+
+#[cfg(feature = "serde")]
+impl Serialize for Bytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Reads the data field, which is a string in data written by 0.1
+#[cfg(feature = "serde")]
+struct ByteBuf(Vec<u8>);
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+/// Reads the data field of formats that write the field names, which can tell bytes and strings
+/// apart but may not convert one to the other
+#[cfg(feature = "serde")]
+struct AnyByteBuf;
+
+#[cfg(feature = "serde")]
+impl<'de> DeserializeSeed<'de> for AnyByteBuf {
+    type Value = ByteBuf;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<ByteBuf, D::Error> {
+        deserializer.deserialize_any(ByteBufVisitor)
+    }
+}
+
+#[cfg(feature = "serde")]
+struct ByteBufVisitor;
+
+#[cfg(feature = "serde")]
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("bytes or a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(ByteBuf(v.as_bytes().to_vec()))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(ByteBuf(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(ByteBuf(v))
+    }
+}
+
+#[cfg(feature = "serde")]
+struct BinaryVisitor<T>(PhantomData<fn() -> T>);
+
+#[cfg(feature = "serde")]
+impl<'de, T> Visitor<'de> for BinaryVisitor<T> {
+    type Value = Encrypted<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("struct Encrypted")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let salt = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let ByteBuf(data) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Encrypted::from_binary_fields(salt, &data).map_err(de::Error::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let (mut salt, mut data) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "salt" => salt = Some(map.next_value()?),
+                "data" => data = Some(map.next_value_seed(AnyByteBuf)?.0),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        let salt = salt.ok_or_else(|| de::Error::missing_field("salt"))?;
+        let data = data.ok_or_else(|| de::Error::missing_field("data"))?;
+        Encrypted::from_binary_fields(salt, &data).map_err(de::Error::custom)
+    }
+}
+
+impl<T> Encrypted<T> {
+    /// Cipher the data was encrypted with
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Parameters the key was derived with
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Salt the key was derived with, or the id of the random key if the data has key slots. The
+    /// header of an age file, which holds the wrapped file key
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Nonce the data was encrypted with, empty for Fernet and age
+    pub fn nonce(&self) -> &[u8] {
+        &self.nonce
+    }
+
+    /// The encrypted data, without the header. The payload of an age file
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Type tag stored by the [`Encryptable`](crate::Encryptable) implementation that encrypted
+    /// this, if it has one
+    pub fn type_tag(&self) -> Option<&str> {
+        self.type_tag.as_deref()
+    }
+
+    /// When this was encrypted, read without the password. `None` for data encrypted before
+    /// the creation time was stored, or if the stored time can not be represented, which fails
+    /// to decrypt as [`DecryptError::Malformed`]
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.created_at_secs().and_then(from_unix_time)
+    }
+
+    /// When this expires, if it was encrypted with [`Config::expires_after`](crate::Config::expires_after).
+    /// `None` if the stored time can not be represented, see [`Encrypted::created_at`]
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        self.expires_at.and_then(from_unix_time)
+    }
+
+    /// Creation time in seconds since the unix epoch, from the header or the Fernet token
+    pub(crate) fn created_at_secs(&self) -> Option<u64> {
+        match self.cipher {
+            Cipher::Fernet => fernet_timestamp(&self.data),
+            _ => self.created_at,
+        }
+    }
+
+    /// Fails with [`DecryptError::Expired`] if the expiry time is before `now`
+    pub(crate) fn check_expiry(&self, now: SystemTime) -> Result<(), DecryptError> {
+        match header_time(self.expires_at)? {
+            Some(expires_at) if expires_at <= now => Err(DecryptError::Expired),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with [`DecryptError::Expired`] if this was created more than `ttl` before `now`, or
+    /// with [`DecryptError::CreatedInFuture`] if it was created more than a minute after `now`.
+    /// A `ttl` too large to add to the creation time never expires
+    pub(crate) fn check_ttl(&self, ttl: Duration, now: SystemTime) -> Result<(), DecryptError> {
+        let created_at = header_time(self.created_at_secs())?
+            .ok_or(DecryptError::Malformed("data has no creation time"))?;
+        if now
+            .checked_add(MAX_CLOCK_SKEW)
+            .is_some_and(|latest| created_at > latest)
+        {
+            return Err(DecryptError::CreatedInFuture);
+        }
+        match created_at.checked_add(ttl) {
+            Some(expires_at) if expires_at < now => Err(DecryptError::Expired),
+            _ => Ok(()),
+        }
+    }
+
+    /// Changes the type this is decrypted as. The type tag, if any, is still checked when
+    /// decrypting
+    pub fn cast<U>(self) -> Encrypted<U> {
+        Encrypted {
+            cipher: self.cipher,
+            kdf: self.kdf,
+            salt: self.salt,
+            nonce: self.nonce,
+            data: self.data,
+            slots: self.slots,
+            type_tag: self.type_tag,
+            key_check: self.key_check,
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+            compression: self.compression,
+            padding: self.padding,
+            _type: PhantomData,
+        }
+    }
+
+    /// Fails with [`DecryptError::TypeMismatch`] unless this was encrypted with `type_tag`
+    pub(crate) fn check_type_tag(&self, type_tag: Option<&str>) -> Result<(), DecryptError> {
+        match self.type_tag.as_deref() == type_tag {
+            true => Ok(()),
+            false => Err(DecryptError::TypeMismatch),
+        }
+    }
+
+    /// Error for data that failed authentication. With a key check the key is known to be
+    /// right, so the data was tampered with
+    pub(crate) fn authentication_error(&self) -> DecryptError {
+        match self.key_check.is_empty() {
+            true => DecryptError::Authentication,
+            false => DecryptError::Tampered,
+        }
+    }
+
+    /// Associated data given to the cipher, the header followed by the caller's data. The header
+    /// is self delimiting, so this can not be ambiguous. The key slots are left out so they can be
+    /// changed without re-encrypting the data, a tampered slot fails to unwrap the key instead.
+    /// The key check is left out too, so readers that skip it can still decrypt the data.
+    ///
+    /// Empty for Fernet, which can not authenticate associated data
+    pub(crate) fn aad(&self, aad: &[u8]) -> Result<Vec<u8>, EncryptError> {
+        if self.cipher == Cipher::Fernet {
+            return Ok(Vec::new());
+        }
+        let mut out = self.encode_header(false, None)?;
+        out.extend_from_slice(aad);
+        Ok(out)
+    }
+
+    /// Encodes this into a versioned binary format
+    ///
+    /// # Format
+    /// All integers are big endian
+    ///
+    /// | size | field |
+    /// |------|-------|
+    /// | 4    | magic, `ENCR` |
+    /// | 1    | format version, currently `1` |
+    /// | 1    | cipher id |
+    /// | 1    | kdf id |
+    /// | n    | kdf parameters, depending on the kdf id |
+    /// | 1    | kdf output length |
+    /// | 1    | salt length, followed by the salt |
+    /// | 1    | nonce length, followed by the nonce |
+    /// | n    | header fields, ended by a `0` tag |
+    /// | n    | ciphertext, until the end of the data |
+    ///
+    /// Cipher ids:
+    /// - `1`: Fernet, the nonce is empty and the ciphertext is the decoded Fernet token. Data
+    ///   written by older versions holds the base64 token, which is still read
+    /// - `2`: AES-256-GCM, 12 byte nonce
+    /// - `3`: ChaCha20-Poly1305, 12 byte nonce
+    /// - `4`: XChaCha20-Poly1305, 24 byte nonce
+    ///
+    /// The AEAD ciphers use everything before the ciphertext as associated data
+    ///
+    /// Kdf ids and their parameters:
+    /// - `1`: PBKDF2 HMAC Sha512, `u32` iterations
+    /// - `2`: Argon2id, `u32` memory in KiB, `u32` iterations, `u32` parallelism
+    /// - `3`: scrypt, `u8` log2 of N, `u32` r, `u32` p
+    /// - `4`: key slots, no parameters. The key is random and the salt is its id
+    /// - `5`: X25519, no parameters, only used in key slots. The salt is the ephemeral public key,
+    ///   the key is HKDF Sha256 of the shared secret with the ephemeral and recipient public keys
+    ///   as salt
+    ///
+    /// [`Cipher::Age`] is written as an age file instead, the header in [`Encrypted::salt`]
+    /// followed by the payload. It has no header fields
+    ///
+    /// Header fields are optional metadata, each one is a `u8` tag, a `u16` length and the value.
+    /// Readers skip fields with tags they do not know, unless the high bit of the tag is set, in
+    /// which case the field is required to be understood and decoding fails. Fields:
+    /// - `0x81`: key slots, left out of the associated data. Each slot is a kdf id, its
+    ///   parameters and output length, then the salt, the nonce and the wrapped key, each
+    ///   prefixed by a `u8` length. The key is encrypted with the cipher of the data and the key id
+    ///   as associated data
+    /// - `0x82`: stream, a `u32` chunk size. Written by [`EncryptWriter`](crate::EncryptWriter),
+    ///   the ciphertext is split into chunks, see there. The nonce is a 32 byte salt, the chunks
+    ///   are encrypted with HKDF Sha256 of the key with that salt and `encryptable stream key` as
+    ///   info
+    /// - `0x83`: type tag, UTF-8, see [`Encryptable::type_tag`](crate::Encryptable::type_tag)
+    /// - `0x84`: creation time, a `u64` of seconds since the unix epoch. Not written for Fernet,
+    ///   its tokens hold their own
+    /// - `0x85`: expiry time, a `u64` of seconds since the unix epoch
+    /// - `0x86`: compression, a `u8` id. `1` is zstd and `2` is raw deflate, see [`Compression`]
+    /// - `0x87`: padding, a `u8` id. `1` is PADMÉ, `2` is power of two and `3` is fixed blocks,
+    ///   followed by the `u32` block size, see [`Padding`]
+    /// - `0x04`: key check, left out of the associated data. 32 bytes of HKDF Sha256 of the key,
+    ///   with the salt as HKDF salt and `encryptable key check` as info. It commits to the key,
+    ///   so a wrong password fails with [`DecryptError::WrongPassword`] before decrypting and
+    ///   data that fails authentication with the right one with [`DecryptError::Tampered`]. It
+    ///   does not make guessing the password any easier than trying to decrypt
+    ///
+    /// # Errors
+    /// Fails with [`EncryptError::Serialization`] if a length does not fit in the format, for
+    /// example a type tag longer than `u16::MAX` bytes. Encrypting and deserializing check the
+    /// lengths, so data encrypted or deserialized by this library always fits
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncryptError> {
+        let mut out = self.header()?;
+        out.reserve_exact(self.data.len());
+        out.extend_from_slice(&self.data);
+        Ok(out)
+    }
+
+    /// Encodes everything in the binary format except the ciphertext, or the age header
+    pub(crate) fn header(&self) -> Result<Vec<u8>, EncryptError> {
+        match self.cipher {
+            Cipher::Age => Ok(self.salt.clone()),
+            _ => self.encode_header(true, None),
+        }
+    }
+
+    /// Header of a stream with chunks of `chunk_size` bytes
+    pub(crate) fn stream_header(&self, chunk_size: u32) -> Result<Vec<u8>, EncryptError> {
+        self.encode_header(true, Some(chunk_size))
+    }
+
+    /// Associated data of each chunk of a stream, see [`Encrypted::aad`]
+    pub(crate) fn stream_aad(&self, chunk_size: u32) -> Result<Vec<u8>, EncryptError> {
+        self.encode_header(false, Some(chunk_size))
+    }
+
+    fn encode_header(&self, full: bool, chunk_size: Option<u32>) -> Result<Vec<u8>, EncryptError> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(self.cipher.id());
+        write_kdf(&mut out, &self.kdf)?;
+        write_short_bytes(&mut out, &self.salt)?;
+        write_short_bytes(&mut out, &self.nonce)?;
+
+        if full && !self.slots.is_empty() {
+            let mut slots = Vec::new();
+            for slot in &self.slots {
+                write_kdf(&mut slots, &slot.kdf)?;
+                for bytes in [&slot.salt, &slot.nonce, &slot.key] {
+                    write_short_bytes(&mut slots, bytes)?;
+                }
+            }
+            write_field(&mut out, KEY_SLOTS_FIELD, &slots)?;
+        }
+        if full && !self.key_check.is_empty() {
+            write_field(&mut out, KEY_CHECK_FIELD, &self.key_check)?;
+        }
+        if let Some(type_tag) = &self.type_tag {
+            write_field(&mut out, TYPE_TAG_FIELD, type_tag.as_bytes())?;
+        }
+        for (field, time) in [
+            (CREATED_AT_FIELD, self.created_at),
+            (EXPIRES_AT_FIELD, self.expires_at),
+        ] {
+            if let Some(time) = time {
+                write_field(&mut out, field, &time.to_be_bytes())?;
+            }
+        }
+        if let Some(compression) = self.compression {
+            write_field(&mut out, COMPRESSION_FIELD, &[compression.id()])?;
+        }
+        if let Some(padding) = self.padding {
+            write_field(&mut out, PADDING_FIELD, &padding.to_bytes())?;
+        }
+        if let Some(chunk_size) = chunk_size {
+            write_field(&mut out, STREAM_FIELD, &chunk_size.to_be_bytes())?;
+        }
+
+        out.push(END_OF_FIELDS);
+        Ok(out)
+    }
+
+    /// Data written by 0.1, which only stored the salt and the Fernet token
+    #[cfg(any(feature = "serde", feature = "bincode"))]
+    pub(crate) fn legacy(salt: [u8; 16], token: &[u8]) -> Result<Self, DecryptError> {
+        Ok(Self {
+            cipher: legacy_cipher(),
+            kdf: legacy_kdf(),
+            salt: salt.to_vec(),
+            nonce: Vec::new(),
+            data: general_purpose::URL_SAFE
+                .decode(token)
+                .map_err(|_| DecryptError::Malformed("Fernet token is not base64"))?,
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        })
+    }
+
+    /// Decodes the salt and data written by binary serde formats and bincode, which are
+    /// [`BINARY_MARKER`] and the binary format, or the salt and Fernet token written by 0.1
+    #[cfg(any(feature = "serde", feature = "bincode"))]
+    fn from_binary_fields(salt: [u8; 16], data: &[u8]) -> Result<Self, DecryptError> {
+        if salt == BINARY_MARKER {
+            Self::from_bytes(data)
+        } else {
+            Self::legacy(salt, data)
+        }
+    }
+
+    /// Decodes the binary format produced by [`Encrypted::to_bytes`], or a binary age file with
+    /// the `age` feature
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecryptError> {
+        #[cfg(feature = "age")]
+        if bytes.starts_with(crate::age::VERSION_PREFIX) {
+            return Self::from_age_file(bytes);
+        }
+        match Self::parse(bytes)? {
+            (encrypted, None) => Ok(encrypted),
+            (_, Some(_)) => Err(DecryptError::Malformed(
+                "data is a stream, use a DecryptReader",
+            )),
+        }
+    }
+
+    /// Decodes the binary format, also returning the chunk size if it is a stream. The data is
+    /// everything after the header
+    pub(crate) fn parse(bytes: &[u8]) -> Result<(Self, Option<u32>), DecryptError> {
+        let mut r = Reader(bytes);
+
+        if r.take(MAGIC.len())? != MAGIC {
+            return Err(DecryptError::Malformed("missing magic bytes"));
+        }
+        match r.u8()? {
+            VERSION => {}
+            v => return Err(DecryptError::UnsupportedVersion(v)),
+        }
+
+        let cipher = r.u8()?;
+        let cipher = Cipher::from_id(cipher).ok_or(DecryptError::UnsupportedCipher(cipher))?;
+        let (algorithm, output_len) = r.kdf()?;
+        let salt = r.length_prefixed()?.to_vec();
+        let nonce = r.length_prefixed()?.to_vec();
+
+        let mut slots = Vec::new();
+        let mut type_tag = None;
+        let mut key_check = Vec::new();
+        let mut created_at = None;
+        let mut expires_at = None;
+        let mut compression = None;
+        let mut padding = None;
+        let mut chunk_size = None;
+        loop {
+            let tag = r.u8()?;
+            if tag == END_OF_FIELDS {
+                break;
+            }
+            let len = u16::from_be_bytes(r.array()?);
+            let value = r.take(len as usize)?;
+            match tag {
+                KEY_SLOTS_FIELD => {
+                    let mut r = Reader(value);
+                    while !r.0.is_empty() {
+                        let (algorithm, output_len) = r.kdf()?;
+                        let salt = r.length_prefixed()?.to_vec();
+                        slots.push(KeySlot {
+                            kdf: KdfParams {
+                                algorithm,
+                                salt_len: salt.len(),
+                                output_len,
+                            },
+                            salt,
+                            nonce: r.length_prefixed()?.to_vec(),
+                            key: r.length_prefixed()?.to_vec(),
+                        });
+                    }
+                }
+                TYPE_TAG_FIELD => {
+                    let value = std::str::from_utf8(value)
+                        .map_err(|_| DecryptError::Malformed("type tag is not valid utf-8"))?;
+                    type_tag = Some(value.to_string());
+                }
+                KEY_CHECK_FIELD => key_check = value.to_vec(),
+                CREATED_AT_FIELD => created_at = Some(Reader(value).u64()?),
+                EXPIRES_AT_FIELD => expires_at = Some(Reader(value).u64()?),
+                COMPRESSION_FIELD => {
+                    let id = Reader(value).u8()?;
+                    compression = Some(
+                        Compression::from_id(id).ok_or(DecryptError::UnsupportedCompression(id))?,
+                    );
+                }
+                PADDING_FIELD => padding = Some(Padding::from_bytes(value)?),
+                STREAM_FIELD => {
+                    let mut r = Reader(value);
+                    chunk_size = Some(r.u32()?);
+                }
+                _ if tag & CRITICAL_FIELD != 0 => {
+                    return Err(DecryptError::Malformed("unknown critical header field"))
+                }
+                _ => {}
+            }
+        }
+
+        let data = r.0.to_vec();
+
+        let encrypted = Self {
+            cipher,
+            kdf: KdfParams {
+                algorithm,
+                salt_len: salt.len(),
+                output_len,
+            },
+            salt,
+            nonce,
+            data,
+            slots,
+            type_tag,
+            key_check,
+            created_at,
+            expires_at,
+            compression,
+            padding,
+            _type: PhantomData,
+        };
+        Ok((encrypted, chunk_size))
+    }
+}
+
+/// Cipher of serde data written before it was stored
+#[cfg(any(feature = "serde", feature = "bincode"))]
+fn legacy_cipher() -> Cipher {
+    Cipher::Fernet
+}
+
+/// Kdf of serde data written before it was stored
+#[cfg(any(feature = "serde", feature = "bincode"))]
+fn legacy_kdf() -> KdfParams {
+    KdfParams::pbkdf2(480_000)
+}
+
+/// Seconds since the unix epoch
+pub(crate) fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+/// The time `secs` seconds after the unix epoch, if `SystemTime` can represent it
+fn from_unix_time(secs: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
+/// Reads a time stored in the header, failing if it can not be represented
+fn header_time(secs: Option<u64>) -> Result<Option<SystemTime>, DecryptError> {
+    secs.map(|secs| from_unix_time(secs).ok_or(DecryptError::Malformed("time is out of range")))
+        .transpose()
+}
+
+/// Writes a header field, failing if the value is longer than its `u16` length allows
+fn write_field(out: &mut Vec<u8>, tag: u8, value: &[u8]) -> Result<(), EncryptError> {
+    let len = u16::try_from(value.len())
+        .map_err(|_| EncryptError::Serialization("header field is too long".into()))?;
+    out.push(tag);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value);
+    Ok(())
+}
+
+/// Writes `bytes` prefixed by their `u8` length, failing if they are longer than 255 bytes
+fn write_short_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> Result<(), EncryptError> {
+    let len = u8::try_from(bytes.len())
+        .map_err(|_| EncryptError::Serialization("salt, nonce or key is too long".into()))?;
+    out.push(len);
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Writes the kdf id, its parameters and the output length
+fn write_kdf(out: &mut Vec<u8>, kdf: &KdfParams) -> Result<(), EncryptError> {
+    match kdf.algorithm {
+        KdfAlgorithm::Pbkdf2Sha512 { iterations } => {
+            out.push(1);
+            out.extend_from_slice(&iterations.to_be_bytes());
+        }
+        KdfAlgorithm::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => {
+            out.push(2);
+            out.extend_from_slice(&memory_kib.to_be_bytes());
+            out.extend_from_slice(&iterations.to_be_bytes());
+            out.extend_from_slice(&parallelism.to_be_bytes());
+        }
+        KdfAlgorithm::Scrypt { log_n, r, p } => {
+            out.push(3);
+            out.push(log_n);
+            out.extend_from_slice(&r.to_be_bytes());
+            out.extend_from_slice(&p.to_be_bytes());
+        }
+        KdfAlgorithm::KeySlots => out.push(4),
+        KdfAlgorithm::X25519 => out.push(5),
+    }
+    let output_len = u8::try_from(kdf.output_len)
+        .map_err(|_| EncryptError::Serialization("kdf output length is too long".into()))?;
+    out.push(output_len);
+    Ok(())
+}
+
+/// Reads from the front of a byte slice, failing on truncated data
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecryptError> {
+        if self.0.len() < n {
+            return Err(DecryptError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecryptError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, DecryptError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DecryptError> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecryptError> {
+        Ok(u64::from_be_bytes(self.array()?))
+    }
+
+    fn length_prefixed(&mut self) -> Result<&'a [u8], DecryptError> {
+        let len = self.u8()? as usize;
+        self.take(len)
+    }
+
+    /// Reads a kdf written by `write_kdf`
+    fn kdf(&mut self) -> Result<(KdfAlgorithm, usize), DecryptError> {
+        let algorithm = match self.u8()? {
+            1 => KdfAlgorithm::Pbkdf2Sha512 {
+                iterations: self.u32()?,
+            },
+            2 => KdfAlgorithm::Argon2id {
+                memory_kib: self.u32()?,
+                iterations: self.u32()?,
+                parallelism: self.u32()?,
+            },
+            3 => KdfAlgorithm::Scrypt {
+                log_n: self.u8()?,
+                r: self.u32()?,
+                p: self.u32()?,
+            },
+            4 => KdfAlgorithm::KeySlots,
+            5 => KdfAlgorithm::X25519,
+            id => return Err(DecryptError::UnsupportedKdf(id)),
+        };
+        Ok((algorithm, self.u8()? as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::{Cipher, DecryptError, EncryptError, Encrypted, KdfParams};
+
+    fn encrypted(kdf: KdfParams) -> Encrypted {
+        Encrypted {
+            cipher: Cipher::Fernet,
+            kdf,
+            salt: (0..kdf.salt_len as u8).collect(),
+            nonce: Vec::new(),
+            data: b"token".to_vec(),
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for kdf in [
+            KdfParams::pbkdf2(480_000),
+            KdfParams::argon2id(19 * 1024, 2, 1),
+            KdfParams::scrypt(17, 8, 1),
+        ] {
+            let encrypted = encrypted(kdf);
+            let bytes = encrypted.to_bytes().unwrap();
+
+            assert_eq!(&bytes[..5], b"ENCR\x01");
+            assert_eq!(Encrypted::from_bytes(&bytes).unwrap(), encrypted);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_and_truncated() {
+        let bytes = encrypted(KdfParams::default()).to_bytes().unwrap();
+
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert!(matches!(
+            <Encrypted>::from_bytes(&version),
+            Err(DecryptError::UnsupportedVersion(2))
+        ));
+
+        let mut cipher = bytes.clone();
+        cipher[5] = 0xff;
+        assert!(matches!(
+            <Encrypted>::from_bytes(&cipher),
+            Err(DecryptError::UnsupportedCipher(0xff))
+        ));
+
+        let mut kdf = bytes.clone();
+        kdf[6] = 0xff;
+        assert!(matches!(
+            <Encrypted>::from_bytes(&kdf),
+            Err(DecryptError::UnsupportedKdf(0xff))
+        ));
+
+        assert!(matches!(
+            <Encrypted>::from_bytes(&bytes[..20]),
+            Err(DecryptError::Truncated)
+        ));
+        assert!(matches!(
+            <Encrypted>::from_bytes(b"not encrypted"),
+            Err(DecryptError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn header_fields() {
+        let bytes = encrypted(KdfParams::default()).to_bytes().unwrap();
+        let fields_at = bytes.len() - "token".len() - 1;
+
+        let mut optional = bytes.clone();
+        optional.splice(fields_at..fields_at, [0x01, 0x00, 0x02, 0xaa, 0xbb]);
+        assert_eq!(
+            Encrypted::from_bytes(&optional).unwrap(),
+            encrypted(KdfParams::default())
+        );
+
+        let mut critical = bytes.clone();
+        critical.splice(fields_at..fields_at, [0xff, 0x00, 0x00]);
+        assert!(matches!(
+            <Encrypted>::from_bytes(&critical),
+            Err(DecryptError::Malformed(_))
+        ));
+
+        let mut too_long = encrypted(KdfParams::default());
+        too_long.type_tag = Some("x".repeat(usize::from(u16::MAX) + 1));
+        assert!(matches!(
+            too_long.to_bytes(),
+            Err(EncryptError::Serialization(_))
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_bytes() {
+        let encrypted = encrypted(KdfParams::default());
+
+        let json = serde_json::to_value(&encrypted).unwrap();
+        assert_eq!(json["salt"], "AAECAwQFBgcICQoLDA0ODw==");
+        assert_eq!(json["data"], "dG9rZW4=");
+        assert_eq!(
+            serde_json::from_value::<Encrypted>(json).unwrap(),
+            encrypted
+        );
+
+        // Byte fields used to be written as arrays of numbers
+        let mut legacy = serde_json::to_value(&encrypted).unwrap();
+        legacy["data"] = serde_json::json!([116, 111, 107, 101, 110]);
+        legacy.as_object_mut().unwrap().remove("slots");
+        assert_eq!(
+            serde_json::from_value::<Encrypted>(legacy).unwrap(),
+            encrypted
+        );
+
+        // Rejected so it can always be encoded in the binary format
+        let mut too_long = serde_json::to_value(&encrypted).unwrap();
+        too_long["type_tag"] = "x".repeat(usize::from(u16::MAX) + 1).into();
+        assert!(serde_json::from_value::<Encrypted>(too_long).is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "bincode"))]
+    #[test]
+    fn serde_bincode() {
+        let encrypted = encrypted(KdfParams::default());
+        let config = bincode::config::standard();
+
+        // The marker and the binary format, prefixed by its length
+        let bytes = bincode::serde::encode_to_vec(&encrypted, config).unwrap();
+        assert_eq!(bytes[..16], super::BINARY_MARKER);
+        assert_eq!(bytes[17..], encrypted.to_bytes().unwrap());
+        let (decoded, _): (Encrypted, _) =
+            bincode::serde::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, encrypted);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() {
+        let mut encrypted = encrypted(KdfParams::pbkdf2(1_000));
+        encrypted.salt = vec![1; 16];
+        encrypted.created_at = Some(1_700_000_000);
+        let config = bincode::config::standard();
+
+        // The marker and the binary format, prefixed by its length
+        let bytes = bincode::encode_to_vec(&encrypted, config).unwrap();
+        assert_eq!(bytes[..16], super::BINARY_MARKER);
+        assert_eq!(bytes[16] as usize, bytes.len() - 17);
+        assert_eq!(bytes[17..], encrypted.to_bytes().unwrap());
+        let (decoded, _): (Encrypted, _) = bincode::decode_from_slice(&bytes, config).unwrap();
+        assert_eq!(decoded, encrypted);
+
+        // Written by this version, must stay readable when fields are added
+        let fixture = b"encryptable ENCR\x2fENCR\x01\x01\x01\x00\x00\x03\xe8\x20\
+            \x10\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\x01\
+            \x00\x84\x00\x08\x00\x00\x00\x00\x65\x53\xf1\x00\x00token";
+        let (decoded, _): (Encrypted, _) = bincode::decode_from_slice(fixture, config).unwrap();
+        assert_eq!(decoded, encrypted);
+
+        let mut unsupported = bytes.clone();
+        unsupported[17 + 4] = 2;
+        assert!(bincode::decode_from_slice::<Encrypted, _>(&unsupported, config).is_err());
+    }
+}
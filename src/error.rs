@@ -0,0 +1,150 @@
+use std::{error::Error, fmt, io};
+
+/// A boxed error from a serializer, key derivation function or other dependency
+pub type BoxError = Box<dyn Error + Send + Sync + 'static>;
+
+/// Errors that can happen while encrypting
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EncryptError {
+    /// The key could not be derived from the password
+    Kdf(BoxError),
+    /// The cipher failed to encrypt the data
+    Cipher(BoxError),
+    /// The value could not be serialized to bytes before encryption
+    Serialization(BoxError),
+    /// The plaintext could not be compressed
+    Compression(BoxError),
+}
+
+/// Errors that can happen while decrypting
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecryptError {
+    /// The password is wrong or the data failed authentication. Only returned for data without
+    /// a key check, which can not tell the two apart
+    Authentication,
+    /// The password is wrong, the key check stored in the data does not match
+    WrongPassword,
+    /// The password is right but the data failed authentication, it was changed or corrupted
+    Tampered,
+    /// The data ends early
+    Truncated,
+    /// The encrypted data is not well-formed
+    Malformed(&'static str),
+    /// The encrypted data uses a format version this library does not understand
+    UnsupportedVersion(u8),
+    /// The encrypted data uses a cipher id this library does not know
+    UnsupportedCipher(u8),
+    /// The encrypted data uses a kdf id this library does not know
+    UnsupportedKdf(u8),
+    /// The encrypted data uses a compression id this library does not know, or whose feature is
+    /// not enabled
+    UnsupportedCompression(u8),
+    /// The encrypted data uses a padding id this library does not know
+    UnsupportedPadding(u8),
+    /// The data decompresses to more than the limit, see
+    /// [`Encryptable::max_decompressed_len`](crate::Encryptable::max_decompressed_len)
+    TooLarge,
+    /// The `DerivedKey` was derived with a different salt or kdf than the encrypted data
+    KeyMismatch,
+    /// The key could not be derived from the password
+    Kdf(BoxError),
+    /// The decrypted bytes could not be deserialized
+    Serialization(BoxError),
+    /// The encrypted data could not be read
+    Io(io::Error),
+    /// The data was encrypted as another type, its type tag does not match
+    TypeMismatch,
+    /// The expiry time of the data, or the time to live given when decrypting, has passed
+    Expired,
+    /// The data was created more than a minute after the current time, so its time to live can
+    /// not be checked. The clock of this machine or of the one that encrypted it is wrong
+    CreatedInFuture,
+}
+
+impl fmt::Display for EncryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Kdf(_) => f.write_str("key derivation failed"),
+            Self::Cipher(_) => f.write_str("encryption failed"),
+            Self::Serialization(_) => f.write_str("failed to serialize plaintext"),
+            Self::Compression(_) => f.write_str("failed to compress plaintext"),
+        }
+    }
+}
+
+impl Error for EncryptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Kdf(e) | Self::Cipher(e) | Self::Serialization(e) | Self::Compression(e) => {
+                Some(e.as_ref())
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecryptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Authentication => f.write_str("wrong password or data failed authentication"),
+            Self::WrongPassword => f.write_str("wrong password"),
+            Self::Tampered => {
+                f.write_str("data failed authentication, it was changed or corrupted")
+            }
+            Self::Truncated => f.write_str("data is truncated"),
+            Self::Malformed(reason) => write!(f, "malformed encrypted data: {reason}"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported format version {v:#04x}"),
+            Self::UnsupportedCipher(id) => write!(f, "unsupported cipher id {id}"),
+            Self::UnsupportedKdf(id) => write!(f, "unsupported kdf id {id}"),
+            Self::UnsupportedCompression(id) => write!(f, "unsupported compression id {id}"),
+            Self::UnsupportedPadding(id) => write!(f, "unsupported padding id {id}"),
+            Self::TooLarge => f.write_str("decompressed data is larger than the limit"),
+            Self::KeyMismatch => f.write_str("key was not derived for this encrypted data"),
+            Self::Kdf(_) => f.write_str("key derivation failed"),
+            Self::Serialization(_) => f.write_str("failed to deserialize plaintext"),
+            Self::Io(_) => f.write_str("failed to read encrypted data"),
+            Self::TypeMismatch => f.write_str("data was encrypted as another type"),
+            Self::Expired => f.write_str("data has expired"),
+            Self::CreatedInFuture => f.write_str("data was created in the future"),
+        }
+    }
+}
+
+impl Error for DecryptError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Kdf(e) | Self::Serialization(e) => Some(e.as_ref()),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Errors that can happen while changing the password of an `Encrypted`
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RekeyError {
+    /// The data could not be decrypted with the old password
+    Decrypt(DecryptError),
+    /// The data could not be encrypted with the new password
+    Encrypt(EncryptError),
+}
+
+impl fmt::Display for RekeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Decrypt(_) => f.write_str("failed to decrypt with the old password"),
+            Self::Encrypt(_) => f.write_str("failed to encrypt with the new password"),
+        }
+    }
+}
+
+impl Error for RekeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Decrypt(e) => Some(e),
+            Self::Encrypt(e) => Some(e),
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{BoxError, DecryptError, EncryptError, Encryptable, SecretBytes};
+
+/// A serde data format used by [`FormatEncrypter`] to turn values into bytes before encryption
+pub trait Format {
+    /// Serializes `value` to bytes
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError>;
+    /// Deserializes a value from bytes
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError>;
+}
+
+/// JSON, using `serde_json`
+pub struct Json;
+
+impl Format for Json {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// bincode with the standard config, requires the `bincode` feature
+#[cfg(feature = "bincode")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Format for Bincode {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(bincode::serde::encode_to_vec(
+            value,
+            bincode::config::standard(),
+        )?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+        let (value, len) = bincode::serde::decode_from_slice(bytes, bincode::config::standard())?;
+        if len != bytes.len() {
+            return Err("trailing bytes after bincode value".into());
+        }
+        Ok(value)
+    }
+}
+
+/// CBOR, using `ciborium`, requires the `cbor` feature
+#[cfg(feature = "cbor")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl Format for Cbor {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        let mut out = Vec::new();
+        ciborium::into_writer(value, &mut out)?;
+        Ok(out)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+        Ok(ciborium::from_reader(bytes)?)
+    }
+}
+
+/// MessagePack, using `rmp-serde`, requires the `msgpack` feature
+#[cfg(feature = "msgpack")]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Format for MessagePack {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, BoxError> {
+        Ok(rmp_serde::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, BoxError> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// `impl Encryptable<T>` for any `T: Serialize + DeserializeOwned`, serializing it with the
+/// format `F` before encryption
+///
+/// Use [`SerdeEncrypter`] for the default format
+///
+/// ```
+/// use encryptable::{Encryptable, FormatEncrypter, Json};
+///
+/// let encrypted = FormatEncrypter::<Json>::encrypt(&vec![1, 2, 3], "password").unwrap();
+/// let decrypted: Vec<i32> = FormatEncrypter::<Json>::decrypt(&encrypted, "password").unwrap();
+/// assert_eq!(decrypted, [1, 2, 3]);
+/// ```
+pub struct FormatEncrypter<F>(PhantomData<F>);
+
+/// `impl Encryptable<T>` for any `T: Serialize + DeserializeOwned`, serialized as JSON
+///
+/// ```
+/// use encryptable::{Encryptable, SerdeEncrypter};
+///
+/// let encrypted = SerdeEncrypter::encrypt(&("id".to_string(), 5), "password").unwrap();
+/// let decrypted: (String, u32) = SerdeEncrypter::decrypt(&encrypted, "password").unwrap();
+/// assert_eq!(decrypted, ("id".to_string(), 5));
+/// ```
+pub type SerdeEncrypter = FormatEncrypter<Json>;
+
+impl<T, F> Encryptable<T> for FormatEncrypter<F>
+where
+    T: Serialize + DeserializeOwned,
+    F: Format,
+{
+    fn to_plaintext(data: &T) -> Result<SecretBytes, EncryptError> {
+        F::serialize(data)
+            .map(SecretBytes::new)
+            .map_err(EncryptError::Serialization)
+    }
+
+    fn from_plaintext(plaintext: SecretBytes) -> Result<T, DecryptError> {
+        F::deserialize(&plaintext).map_err(DecryptError::Serialization)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use crate::{Config, DecryptError, Encryptable, Encrypted, Format, FormatEncrypter, KdfParams};
+
+    /// Salt and Fernet token written by 0.1, as in `baseline_serde`
+    const BASELINE_SALT: &[u8] =
+        b"\x0d\x6d\x96\x71\x46\x22\xbe\x03\x02\x70\xfe\x35\x68\x78\xd2\x2a";
+    const BASELINE_TOKEN: &[u8] = b"gAAAAABq0_4HCO7Ll0R8j-6OPUgYyX9TN120_Fd7FKcXGKihA0T700e2jA23oleJjbZ_VRYzjt5sPq1MEc4RifMNNZjpc1hVBg==";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+        tags: Vec<String>,
+    }
+
+    fn round_trip<F: Format>() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let user = User {
+            name: "name".to_string(),
+            age: 20,
+            tags: vec!["a".to_string(), "b".to_string()],
+        };
+
+        let encrypted = FormatEncrypter::<F>::encrypt_with(&user, "password", &config).unwrap();
+        let decrypted: User = FormatEncrypter::<F>::decrypt(&encrypted, "password").unwrap();
+        assert_eq!(decrypted, user);
+
+        let bytes = F::serialize(&encrypted).unwrap();
+        assert_eq!(
+            F::deserialize::<Encrypted<User>>(&bytes).unwrap(),
+            encrypted
+        );
+
+        let wrong_type: Result<(u8, u8), _> =
+            FormatEncrypter::<F>::decrypt(&encrypted.cast(), "password");
+        assert!(matches!(wrong_type, Err(DecryptError::Serialization(_))));
+    }
+
+    /// Reads `parts` written by 0.1 in format `F`
+    fn legacy<F: Format>(parts: &[&[u8]]) {
+        let baseline = Encrypted::legacy(BASELINE_SALT.try_into().unwrap(), BASELINE_TOKEN);
+        let encrypted: Encrypted = F::deserialize(&parts.concat()).unwrap();
+        assert_eq!(encrypted, baseline.unwrap());
+    }
+
+    #[test]
+    fn json() {
+        round_trip::<crate::Json>();
+        legacy::<crate::Json>(&[
+            br#"{"salt":[13,109,150,113,70,34,190,3,2,112,254,53,104,120,210,42],"data":""#,
+            BASELINE_TOKEN,
+            br#""}"#,
+        ]);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode() {
+        round_trip::<crate::Bincode>();
+        legacy::<crate::Bincode>(&[BASELINE_SALT, b"\x64", BASELINE_TOKEN]);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor() {
+        round_trip::<crate::Cbor>();
+        legacy::<crate::Cbor>(&[
+            b"\xa2\x64salt\x90\x0d\x18\x6d\x18\x96\x18\x71\x18\x46\x18\x22\x18\xbe\x03\x02\
+              \x18\x70\x18\xfe\x18\x35\x18\x68\x18\x78\x18\xd2\x18\x2a\x64data\x78\x64",
+            BASELINE_TOKEN,
+        ]);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack() {
+        round_trip::<crate::MessagePack>();
+        legacy::<crate::MessagePack>(&[
+            b"\x92\xdc\x00\x10\x0d\x6d\xcc\x96\x71\x46\x22\xcc\xbe\x03\x02\x70\xcc\xfe\x35\x68\
+              \x78\xcc\xd2\x2a\xd9\x64",
+            BASELINE_TOKEN,
+        ]);
+    }
+}
@@ -0,0 +1,279 @@
+use argon2::{Algorithm, Argon2, Version};
+#[cfg(feature = "bincode")]
+use bincode::{Decode, Encode};
+use pbkdf2::pbkdf2_hmac;
+use rand::{thread_rng, RngCore};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use sha2::Sha512;
+use zeroize::Zeroizing;
+
+use crate::{BoxError, DecryptError};
+
+/// Key derivation algorithm along with its cost parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+#[non_exhaustive]
+pub enum KdfAlgorithm {
+    /// PBKDF2 HMAC with Sha512
+    Pbkdf2Sha512 {
+        /// Number of rounds
+        iterations: u32,
+    },
+    /// Argon2id (v0x13), memory-hard
+    Argon2id {
+        /// Memory cost in KiB
+        memory_kib: u32,
+        /// Number of passes over the memory
+        iterations: u32,
+        /// Degree of parallelism
+        parallelism: u32,
+    },
+    /// scrypt, memory-hard
+    Scrypt {
+        /// Log2 of the CPU/memory cost `N`
+        log_n: u8,
+        /// Block size
+        r: u32,
+        /// Parallelization
+        p: u32,
+    },
+    /// No kdf, the key is random and is wrapped by the key slots of the `Encrypted`. See
+    /// [`DerivedKey::with_key_slots`](crate::DerivedKey::with_key_slots)
+    KeySlots,
+    /// No kdf, X25519 key agreement with HKDF Sha256, used by the key slots of recipients. The
+    /// salt is the ephemeral public key. See [`RecipientEncrypter`](crate::RecipientEncrypter)
+    X25519,
+}
+
+/// Parameters used to turn a password into a key. These are chosen when encrypting and stored
+/// in the `Encrypted`, so they can be raised later without breaking old data.
+///
+/// The default is Argon2id with 19 MiB of memory, 2 iterations and no parallelism, a 16 byte salt
+/// and a 32 byte key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+pub struct KdfParams {
+    /// Algorithm and its cost
+    pub algorithm: KdfAlgorithm,
+    /// Length of the random salt in bytes
+    pub salt_len: usize,
+    /// Length of the derived key in bytes
+    pub output_len: usize,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::argon2id(19 * 1024, 2, 1)
+    }
+}
+
+impl KdfParams {
+    /// PBKDF2 HMAC Sha512 with `iterations` rounds and the default salt and output lengths
+    pub fn pbkdf2(iterations: u32) -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Pbkdf2Sha512 { iterations },
+            salt_len: 16,
+            output_len: 32,
+        }
+    }
+
+    /// Argon2id with the given memory cost (in KiB), passes and parallelism, and the default salt
+    /// and output lengths
+    pub fn argon2id(memory_kib: u32, iterations: u32, parallelism: u32) -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            },
+            ..Self::pbkdf2(1)
+        }
+    }
+
+    /// scrypt with `N = 2^log_n`, block size `r` and parallelization `p`, and the default salt and
+    /// output lengths
+    pub fn scrypt(log_n: u8, r: u32, p: u32) -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Scrypt { log_n, r, p },
+            ..Self::pbkdf2(1)
+        }
+    }
+
+    /// Parameters of a random key stored in key slots, the salt is used as the id of the key
+    pub(crate) fn key_slots() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::KeySlots,
+            ..Self::pbkdf2(1)
+        }
+    }
+
+    /// Parameters of a recipient key slot, the salt is the ephemeral public key
+    pub(crate) fn x25519() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::X25519,
+            salt_len: 32,
+            output_len: 32,
+        }
+    }
+
+    /// Generates a random salt of `salt_len` bytes
+    pub(crate) fn generate_salt(&self) -> Vec<u8> {
+        let mut salt = vec![0u8; self.salt_len];
+        thread_rng().fill_bytes(&mut salt);
+        salt
+    }
+
+    /// Fails with [`DecryptError::Malformed`] if deriving the key would cost more than `limits`
+    /// allows. The parameters come from the data being decrypted, so they are checked before
+    /// anything is allocated
+    pub(crate) fn check_limits(&self, limits: &KdfLimits) -> Result<(), DecryptError> {
+        let within = match self.algorithm {
+            KdfAlgorithm::Pbkdf2Sha512 { iterations } => iterations <= limits.max_iterations,
+            KdfAlgorithm::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                memory_kib <= limits.max_memory_kib
+                    && iterations <= limits.max_passes
+                    && parallelism <= limits.max_parallelism
+            }
+            KdfAlgorithm::Scrypt { log_n, r, p } => {
+                // scrypt uses 128 * r * N bytes
+                let memory_kib = 1u64
+                    .checked_shl(log_n.into())
+                    .and_then(|n| n.checked_mul(r.into()))
+                    .map(|blocks| blocks / 8);
+                log_n <= limits.max_log_n
+                    && memory_kib.is_some_and(|kib| kib <= limits.max_memory_kib.into())
+                    && p <= limits.max_parallelism
+            }
+            KdfAlgorithm::KeySlots | KdfAlgorithm::X25519 => true,
+        };
+        match within {
+            true => Ok(()),
+            false => Err(DecryptError::Malformed(
+                "kdf parameters are above the limits for decrypting",
+            )),
+        }
+    }
+
+    /// Derives `output_len` bytes from `password` and `salt`
+    pub(crate) fn derive(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+    ) -> Result<Zeroizing<Vec<u8>>, BoxError> {
+        if self.salt_len > u8::MAX as usize || self.output_len > u8::MAX as usize {
+            return Err("salt and output lengths must be at most 255 bytes".into());
+        }
+        if salt.len() != self.salt_len {
+            return Err("salt length does not match the kdf parameters".into());
+        }
+
+        let mut out = Zeroizing::new(vec![0u8; self.output_len]);
+        match self.algorithm {
+            KdfAlgorithm::Pbkdf2Sha512 { iterations } => {
+                if iterations == 0 {
+                    return Err("pbkdf2 needs at least one iteration".into());
+                }
+                pbkdf2_hmac::<Sha512>(password, salt, iterations, &mut out);
+            }
+            KdfAlgorithm::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params =
+                    argon2::Params::new(memory_kib, iterations, parallelism, Some(out.len()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, &mut out)?;
+            }
+            KdfAlgorithm::Scrypt { log_n, r, p } => {
+                let params = scrypt::Params::new(log_n, r, p, out.len())?;
+                scrypt::scrypt(password, salt, &params, &mut out)?;
+            }
+            KdfAlgorithm::KeySlots => {
+                return Err("the key is stored in key slots, not derived from a password".into())
+            }
+            KdfAlgorithm::X25519 => {
+                return Err(
+                    "the key is agreed with a secret key, not derived from a password".into(),
+                )
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Highest kdf costs accepted when decrypting. The kdf parameters are read from the data, so
+/// without limits a crafted `Encrypted` could make decryption allocate any amount of memory or
+/// run for hours. Data asking for more fails with [`DecryptError::Malformed`] before the key is
+/// derived.
+///
+/// The default allows 1 GiB of memory, 10 million PBKDF2 iterations, 10 Argon2id passes, scrypt
+/// up to `log_n` 20 and a parallelism of 16. Raise them with
+/// [`Encryptable::kdf_limits`](crate::Encryptable::kdf_limits),
+/// [`DerivedKey::unlock_with_limits`](crate::DerivedKey::unlock_with_limits) or
+/// [`KeyCache::with_limits`](crate::KeyCache::with_limits) if data was encrypted with higher
+/// costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfLimits {
+    max_memory_kib: u32,
+    max_iterations: u32,
+    max_passes: u32,
+    max_log_n: u8,
+    max_parallelism: u32,
+}
+
+impl Default for KdfLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_kib: 1024 * 1024,
+            max_iterations: 10_000_000,
+            max_passes: 10,
+            max_log_n: 20,
+            max_parallelism: 16,
+        }
+    }
+}
+
+impl KdfLimits {
+    /// Creates the default limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Most memory Argon2id and scrypt may use, in KiB
+    pub fn max_memory_kib(mut self, max_memory_kib: u32) -> Self {
+        self.max_memory_kib = max_memory_kib;
+        self
+    }
+
+    /// Most PBKDF2 iterations
+    pub fn max_iterations(mut self, max_iterations: u32) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Most Argon2id passes over the memory
+    pub fn max_passes(mut self, max_passes: u32) -> Self {
+        self.max_passes = max_passes;
+        self
+    }
+
+    /// Highest scrypt `log_n`
+    pub fn max_log_n(mut self, max_log_n: u8) -> Self {
+        self.max_log_n = max_log_n;
+        self
+    }
+
+    /// Highest Argon2id parallelism and scrypt `p`
+    pub fn max_parallelism(mut self, max_parallelism: u32) -> Self {
+        self.max_parallelism = max_parallelism;
+        self
+    }
+}
@@ -0,0 +1,694 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    marker::PhantomData,
+    time::{Duration, SystemTime},
+};
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+use rand::{thread_rng, RngCore};
+
+use crate::{
+    encrypted::unix_time,
+    padding::unpad,
+    recipient::open_recipient_slots,
+    slots::{check_key_slot_count, open_key_slots},
+    Cipher, Compression, Config, DecryptError, EncryptError, Encrypted, KdfAlgorithm, KdfLimits,
+    KdfParams, KeySlot, Padding, PublicKey, SecretBytes, SecretKey,
+};
+
+/// HKDF info of the key check
+const KEY_CHECK_INFO: &[u8] = b"encryptable key check";
+/// Length of the key check
+const KEY_CHECK_LEN: usize = 32;
+/// HKDF info of the key of a stream
+const STREAM_KEY_INFO: &[u8] = b"encryptable stream key";
+
+/// A key derived from a password and salt. Deriving is the slow part of encryption, so a
+/// `DerivedKey` can be made once and then used to encrypt or decrypt many items cheaply with
+/// [`Encryptable::encrypt_with_key`](crate::Encryptable::encrypt_with_key) and
+/// [`Encryptable::decrypt_with_key`](crate::Encryptable::decrypt_with_key).
+///
+/// Every item gets its own random nonce, but they all share the salt of the key. The key is wiped
+/// from memory when dropped.
+///
+/// A key made with [`DerivedKey::with_key_slots`] is random instead, and the items encrypted
+/// with it get a copy of its key slots.
+///
+/// Items get an expiry time if the `Config` had one, counted from when each item is encrypted,
+/// and are compressed and padded as it says.
+///
+/// With [`Cipher::Age`] the key is the file key of an age header, which is used as the salt. The
+/// items encrypted with it are age files sharing that header.
+#[derive(Clone)]
+pub struct DerivedKey {
+    cipher: Cipher,
+    kdf: KdfParams,
+    salt: Vec<u8>,
+    key: Zeroizing<Vec<u8>>,
+    slots: Vec<KeySlot>,
+    expires_after: Option<Duration>,
+    compression: Option<Compression>,
+    padding: Option<Padding>,
+}
+
+impl DerivedKey {
+    /// Derives a key from `password` and a fresh random salt, using the cipher and kdf of
+    /// `config`
+    pub fn new(password: &str, config: &Config) -> Result<Self, EncryptError> {
+        let cipher = config.cipher;
+        if cipher == Cipher::Age {
+            #[cfg(feature = "age")]
+            return crate::age::passphrase_header(password, &config.kdf).map(|(header, key)| {
+                Self {
+                    expires_after: config.expires_after,
+                    compression: config.compression,
+                    padding: config.padding,
+                    ..Self::for_age(config.kdf, header, key)
+                }
+            });
+            #[cfg(not(feature = "age"))]
+            return Err(age_disabled());
+        }
+        if config.kdf.output_len != cipher.key_len() {
+            return Err(EncryptError::Kdf(
+                format!("{cipher:?} needs a {} byte key", cipher.key_len()).into(),
+            ));
+        }
+
+        let salt = config.kdf.generate_salt();
+        let key = config
+            .kdf
+            .derive(password.as_bytes(), &salt)
+            .map_err(EncryptError::Kdf)?;
+
+        Ok(Self {
+            cipher,
+            kdf: config.kdf,
+            salt,
+            key,
+            slots: Vec::new(),
+            expires_after: config.expires_after,
+            compression: config.compression,
+            padding: config.padding,
+        })
+    }
+
+    /// Generates a random key and wraps it in a key slot for each of `passwords`, using the cipher
+    /// and kdf of `config`. Any of the passwords can decrypt the items encrypted with this key,
+    /// see [`Encrypted::add_key_slot`] to change the passwords later.
+    ///
+    /// The kdf runs once per password, up to 32 passwords. Not supported by [`Cipher::Fernet`],
+    /// which can not bind a slot to its key, or by [`Cipher::Age`]
+    pub fn with_key_slots(passwords: &[&str], config: &Config) -> Result<Self, EncryptError> {
+        if config.cipher == Cipher::Age {
+            return Err(EncryptError::Cipher(
+                "age does not support key slots".into(),
+            ));
+        }
+        if passwords.is_empty() {
+            return Err(EncryptError::Kdf("at least one password is needed".into()));
+        }
+        check_key_slot_count(passwords.len())?;
+
+        let mut key = Self::random(config);
+        key.slots = passwords
+            .iter()
+            .map(|password| KeySlot::new(key.cipher, &key.salt, &key.key, password, config.kdf))
+            .collect::<Result<_, _>>()?;
+        Ok(key)
+    }
+
+    /// Generates a random key and wraps it for each of `recipients`, using the cipher of
+    /// `config`. The secret key of any recipient can decrypt the items encrypted with this key.
+    /// Up to 32 recipients, not supported by [`Cipher::Fernet`]. With [`Cipher::Age`] they are
+    /// X25519 stanzas of the age header, without a limit
+    pub fn with_recipients(
+        recipients: &[PublicKey],
+        config: &Config,
+    ) -> Result<Self, EncryptError> {
+        if config.cipher == Cipher::Age {
+            #[cfg(feature = "age")]
+            return crate::age::recipients_header(recipients).map(|(header, key)| Self {
+                expires_after: config.expires_after,
+                compression: config.compression,
+                padding: config.padding,
+                ..Self::for_age(KdfParams::x25519(), header, key)
+            });
+            #[cfg(not(feature = "age"))]
+            return Err(age_disabled());
+        }
+        if recipients.is_empty() {
+            return Err(EncryptError::Kdf("at least one recipient is needed".into()));
+        }
+        check_key_slot_count(recipients.len())?;
+
+        let mut key = Self::random(config);
+        key.slots = recipients
+            .iter()
+            .map(|recipient| KeySlot::for_recipient(key.cipher, &key.salt, &key.key, recipient))
+            .collect::<Result<_, _>>()?;
+        Ok(key)
+    }
+
+    /// File key of an age file, with its `header` as the salt
+    #[cfg(feature = "age")]
+    fn for_age(kdf: KdfParams, header: Vec<u8>, file_key: Zeroizing<Vec<u8>>) -> Self {
+        Self {
+            cipher: Cipher::Age,
+            kdf,
+            salt: header,
+            key: file_key,
+            slots: Vec::new(),
+            expires_after: None,
+            compression: None,
+            padding: None,
+        }
+    }
+
+    /// Random key without any key slots
+    fn random(config: &Config) -> Self {
+        let cipher = config.cipher;
+        let kdf = KdfParams::key_slots();
+        let mut key = Zeroizing::new(vec![0u8; cipher.key_len()]);
+        thread_rng().fill_bytes(&mut key);
+
+        Self {
+            cipher,
+            kdf,
+            salt: kdf.generate_salt(),
+            key,
+            slots: Vec::new(),
+            expires_after: config.expires_after,
+            compression: config.compression,
+            padding: config.padding,
+        }
+    }
+
+    /// Derives the key that `encrypted` was encrypted with from `password`, or unwraps it from
+    /// the first key slot `password` unlocks. The returned key encrypts new items with the same
+    /// cipher, kdf and salt
+    pub fn unlock<T>(encrypted: &Encrypted<T>, password: &str) -> Result<Self, DecryptError> {
+        Self::unlock_with_limits(encrypted, password, &KdfLimits::default())
+    }
+
+    /// Like [`DerivedKey::unlock`], failing with [`DecryptError::Malformed`] instead of deriving
+    /// the key if the kdf costs more than `limits`
+    pub fn unlock_with_limits<T>(
+        encrypted: &Encrypted<T>,
+        password: &str,
+        limits: &KdfLimits,
+    ) -> Result<Self, DecryptError> {
+        if encrypted.cipher == Cipher::Age {
+            // The work factor of the scrypt stanza is the kdf
+            encrypted.kdf.check_limits(limits)?;
+            #[cfg(feature = "age")]
+            return crate::age::unlock_with_passphrase(&encrypted.salt, password)
+                .map(|key| Self::for_age(encrypted.kdf, encrypted.salt.clone(), key));
+            #[cfg(not(feature = "age"))]
+            return Err(DecryptError::UnsupportedCipher(encrypted.cipher.id()));
+        }
+
+        let (key, slots) = if encrypted.kdf.algorithm == KdfAlgorithm::KeySlots {
+            let (_, key) = open_key_slots(
+                encrypted.cipher,
+                &encrypted.salt,
+                &encrypted.slots,
+                password,
+                limits,
+            )?;
+            (key, encrypted.slots.clone())
+        } else {
+            encrypted.kdf.check_limits(limits)?;
+            let key = encrypted
+                .kdf
+                .derive(password.as_bytes(), &encrypted.salt)
+                .map_err(DecryptError::Kdf)?;
+            (key, Vec::new())
+        };
+
+        let key = Self {
+            cipher: encrypted.cipher,
+            kdf: encrypted.kdf,
+            salt: encrypted.salt.clone(),
+            key,
+            slots,
+            expires_after: None,
+            compression: None,
+            padding: None,
+        };
+        match key.checks(encrypted) {
+            true => Ok(key),
+            // The slot was unlocked, so the password is right
+            false if !key.slots.is_empty() => Err(DecryptError::Tampered),
+            false => Err(DecryptError::WrongPassword),
+        }
+    }
+
+    /// Unwraps the key of `encrypted` from the recipient key slot of `secret_key`, or from its
+    /// X25519 stanza for [`Cipher::Age`]
+    pub fn unlock_with_secret_key<T>(
+        encrypted: &Encrypted<T>,
+        secret_key: &SecretKey,
+    ) -> Result<Self, DecryptError> {
+        if encrypted.cipher == Cipher::Age {
+            #[cfg(feature = "age")]
+            return crate::age::unlock_with_secret_key(&encrypted.salt, secret_key)
+                .map(|key| Self::for_age(encrypted.kdf, encrypted.salt.clone(), key));
+            #[cfg(not(feature = "age"))]
+            return Err(DecryptError::UnsupportedCipher(encrypted.cipher.id()));
+        }
+        if encrypted.kdf.algorithm != KdfAlgorithm::KeySlots {
+            return Err(DecryptError::KeyMismatch);
+        }
+        let key = open_recipient_slots(
+            encrypted.cipher,
+            &encrypted.salt,
+            &encrypted.slots,
+            secret_key,
+        )?;
+
+        let key = Self {
+            cipher: encrypted.cipher,
+            kdf: encrypted.kdf,
+            salt: encrypted.salt.clone(),
+            key,
+            slots: encrypted.slots.clone(),
+            expires_after: None,
+            compression: None,
+            padding: None,
+        };
+        match key.checks(encrypted) {
+            true => Ok(key),
+            false => Err(DecryptError::Tampered),
+        }
+    }
+
+    /// Parameters the key was derived with
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Salt the key was derived with
+    pub fn salt(&self) -> &[u8] {
+        &self.salt
+    }
+
+    /// Whether `encrypted` was made with a key derived like this one
+    pub fn matches<T>(&self, encrypted: &Encrypted<T>) -> bool {
+        self.kdf == encrypted.kdf && self.salt == encrypted.salt
+    }
+
+    /// Cipher the key is used with
+    pub(crate) fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// The raw key
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Key of one stream, HKDF Sha256 of this key with the random salt of the stream. Each stream
+    /// gets its own key, so the chunk nonces only have to be unique within a stream
+    pub(crate) fn for_stream(&self, stream_salt: &[u8]) -> Self {
+        let mut key = Zeroizing::new(vec![0u8; self.key.len()]);
+        Hkdf::<Sha256>::new(Some(stream_salt), &self.key)
+            .expand(STREAM_KEY_INFO, &mut key)
+            .expect("cipher keys are a valid hkdf output length");
+        Self {
+            key,
+            ..self.clone()
+        }
+    }
+
+    /// Value stored with the data that commits to the key, see [`Encrypted::to_bytes`]
+    fn key_check(&self) -> Vec<u8> {
+        let mut out = vec![0u8; KEY_CHECK_LEN];
+        Hkdf::<Sha256>::new(Some(&self.salt), &self.key)
+            .expand(KEY_CHECK_INFO, &mut out)
+            .expect("32 bytes is a valid hkdf output length");
+        out
+    }
+
+    /// Whether `encrypted` was encrypted with this key according to its key check. Data
+    /// without a key check passes
+    pub(crate) fn checks<T>(&self, encrypted: &Encrypted<T>) -> bool {
+        encrypted.key_check.is_empty() || bool::from(self.key_check().ct_eq(&encrypted.key_check))
+    }
+
+    /// `Encrypted` without data made with this key, holding `nonce`. Fails if the expiry time
+    /// can not be represented. Age files have no key check or creation time
+    pub(crate) fn template<T>(&self, nonce: Vec<u8>) -> Result<Encrypted<T>, EncryptError> {
+        let now = SystemTime::now();
+        let expires_at = self
+            .expires_after
+            .map(|duration| {
+                now.checked_add(duration).map(unix_time).ok_or_else(|| {
+                    EncryptError::Serialization("expiry time is out of range".into())
+                })
+            })
+            .transpose()?;
+        Ok(Encrypted {
+            cipher: self.cipher,
+            kdf: self.kdf,
+            salt: self.salt.clone(),
+            nonce,
+            data: Vec::new(),
+            slots: self.slots.clone(),
+            type_tag: None,
+            key_check: match self.cipher {
+                Cipher::Age => Vec::new(),
+                _ => self.key_check(),
+            },
+            created_at: (!matches!(self.cipher, Cipher::Fernet | Cipher::Age))
+                .then(|| unix_time(now)),
+            expires_at,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        })
+    }
+
+    /// Encrypts `plaintext` with a new nonce, storing `type_tag` and authenticating it and `aad`
+    pub(crate) fn seal<T>(
+        &self,
+        plaintext: &[u8],
+        type_tag: Option<&str>,
+        aad: &[u8],
+    ) -> Result<Encrypted<T>, EncryptError> {
+        if type_tag.is_some_and(|tag| tag.len() > u16::MAX as usize) {
+            return Err(EncryptError::Serialization("type tag is too long".into()));
+        }
+
+        let mut encrypted = self.template(self.cipher.generate_nonce())?;
+        encrypted.type_tag = type_tag.map(str::to_string);
+        self.encrypt_into(encrypted, plaintext, aad, self.compression, self.padding)
+    }
+
+    /// Encrypts `plaintext` with a new nonce, keeping the type tag and the creation and expiry
+    /// times of `old` and binding it to the same `aad`. Fernet tokens always hold the current time
+    /// and age files none.
+    /// The compression and padding of `old` are kept unless this key sets its own
+    pub(crate) fn reseal<T>(
+        &self,
+        plaintext: &[u8],
+        old: &Encrypted<T>,
+        aad: &[u8],
+    ) -> Result<Encrypted<T>, EncryptError> {
+        let mut encrypted = self.template(self.cipher.generate_nonce())?;
+        encrypted.type_tag = old.type_tag.clone();
+        if !matches!(self.cipher, Cipher::Fernet | Cipher::Age) {
+            encrypted.created_at = old.created_at_secs().or(encrypted.created_at);
+        }
+        encrypted.expires_at = old.expires_at;
+        self.encrypt_into(
+            encrypted,
+            plaintext,
+            aad,
+            self.compression.or(old.compression),
+            self.padding.or(old.padding),
+        )
+    }
+
+    /// Encrypts `plaintext` into the data of `encrypted`, compressing and padding it first
+    fn encrypt_into<T>(
+        &self,
+        mut encrypted: Encrypted<T>,
+        plaintext: &[u8],
+        aad: &[u8],
+        compression: Option<Compression>,
+        padding: Option<Padding>,
+    ) -> Result<Encrypted<T>, EncryptError> {
+        if self.cipher == Cipher::Fernet && !aad.is_empty() {
+            return Err(EncryptError::Cipher(
+                "fernet does not support associated data".into(),
+            ));
+        }
+        if self.cipher == Cipher::Fernet && encrypted.expires_at.is_some() {
+            return Err(EncryptError::Cipher(
+                "fernet does not support an expiry time".into(),
+            ));
+        }
+        if self.cipher == Cipher::Fernet && encrypted.type_tag.is_some() {
+            return Err(EncryptError::Cipher(
+                "fernet does not support a type tag".into(),
+            ));
+        }
+        if self.cipher == Cipher::Fernet && compression.is_some() {
+            return Err(EncryptError::Cipher(
+                "fernet does not support compression".into(),
+            ));
+        }
+        if self.cipher == Cipher::Fernet && padding.is_some() {
+            return Err(EncryptError::Cipher(
+                "fernet does not support padding".into(),
+            ));
+        }
+        // The age format has nowhere to store or authenticate any of these
+        if self.cipher == Cipher::Age
+            && (!aad.is_empty()
+                || encrypted.expires_at.is_some()
+                || encrypted.type_tag.is_some()
+                || compression.is_some()
+                || padding.is_some())
+        {
+            return Err(EncryptError::Cipher(
+                "age does not support associated data, expiry times, type tags, compression or padding"
+                    .into(),
+            ));
+        }
+        #[cfg(feature = "age")]
+        if self.cipher == Cipher::Age {
+            encrypted.data = crate::age::encrypt_payload(&self.key, plaintext)?;
+            return Ok(encrypted);
+        }
+
+        let compressed;
+        let mut plaintext = plaintext;
+        if let Some(compression) = compression {
+            compressed = compression
+                .compress(plaintext)
+                .map_err(EncryptError::Compression)?;
+            // Data that does not get smaller is stored as it is
+            if compressed.len() < plaintext.len() {
+                plaintext = &compressed;
+                encrypted.compression = Some(compression);
+            }
+        }
+        let padded;
+        if let Some(padding) = padding {
+            padded = padding
+                .pad(plaintext)
+                .map_err(|e| EncryptError::Cipher(e.into()))?;
+            plaintext = &padded;
+            encrypted.padding = Some(padding);
+        }
+
+        encrypted.data = self
+            .cipher
+            .encrypt(&self.key, &encrypted.nonce, &encrypted.aad(aad)?, plaintext)
+            .map_err(EncryptError::Cipher)?;
+        Ok(encrypted)
+    }
+
+    /// Decrypts `encrypted`, checking `aad`, removes the padding and decompresses it up to
+    /// `max_len` bytes
+    pub(crate) fn open<T>(
+        &self,
+        encrypted: &Encrypted<T>,
+        aad: &[u8],
+        max_len: usize,
+    ) -> Result<SecretBytes, DecryptError> {
+        if !self.matches(encrypted) {
+            return Err(DecryptError::KeyMismatch);
+        }
+        if !self.checks(encrypted) {
+            return Err(DecryptError::WrongPassword);
+        }
+        encrypted.check_expiry(SystemTime::now())?;
+        if encrypted.cipher == Cipher::Fernet && !aad.is_empty() {
+            return Err(encrypted.authentication_error());
+        }
+        // Fernet does not authenticate the header, so these could only have been added to it
+        if encrypted.cipher == Cipher::Fernet
+            && (encrypted.type_tag.is_some()
+                || encrypted.compression.is_some()
+                || encrypted.padding.is_some())
+        {
+            return Err(DecryptError::Malformed(
+                "fernet data has a type tag, compression or padding",
+            ));
+        }
+        if encrypted.cipher == Cipher::Age && !aad.is_empty() {
+            return Err(DecryptError::Authentication);
+        }
+        #[cfg(feature = "age")]
+        if encrypted.cipher == Cipher::Age {
+            return crate::age::decrypt_payload(&self.key, &encrypted.data);
+        }
+
+        let header_aad = encrypted
+            .aad(aad)
+            .map_err(|_| DecryptError::Malformed("header field is too long"))?;
+        let plaintext = encrypted
+            .cipher
+            .decrypt(&self.key, &encrypted.nonce, &header_aad, &encrypted.data)
+            .map_err(|e| match e {
+                DecryptError::Authentication => encrypted.authentication_error(),
+                e => e,
+            })?;
+        let plaintext = match encrypted.padding {
+            Some(_) => unpad(plaintext)?,
+            None => plaintext,
+        };
+        match encrypted.compression {
+            Some(compression) => compression.decompress(&plaintext, max_len),
+            None => Ok(plaintext),
+        }
+    }
+}
+
+/// Error for [`Cipher::Age`] without the `age` feature
+#[cfg(not(feature = "age"))]
+fn age_disabled() -> EncryptError {
+    EncryptError::Cipher("age requires the age feature".into())
+}
+
+impl fmt::Debug for DerivedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivedKey")
+            .field("cipher", &self.cipher)
+            .field("kdf", &self.kdf)
+            .field("salt", &self.salt)
+            .field("slots", &self.slots)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Caches the keys derived from one password, so decrypting many `Encrypted` that share a salt
+/// only runs the kdf once per salt. Holds at most `capacity` keys, dropping the least recently
+/// used one when full. The password and keys are wiped from memory when dropped.
+pub struct KeyCache {
+    password: Zeroizing<String>,
+    capacity: usize,
+    limits: KdfLimits,
+    keys: VecDeque<DerivedKey>,
+}
+
+impl KeyCache {
+    /// Creates an empty cache for `password` holding at most `capacity` keys
+    pub fn new(password: &str, capacity: usize) -> Self {
+        Self::with_limits(password, capacity, KdfLimits::default())
+    }
+
+    /// Like [`KeyCache::new`], deriving keys with [`DerivedKey::unlock_with_limits`]
+    pub fn with_limits(password: &str, capacity: usize, limits: KdfLimits) -> Self {
+        Self {
+            password: Zeroizing::new(password.to_string()),
+            capacity: capacity.max(1),
+            limits,
+            keys: VecDeque::new(),
+        }
+    }
+
+    /// Returns the key for `encrypted`, deriving it if it is not cached
+    pub fn key<T>(&mut self, encrypted: &Encrypted<T>) -> Result<&DerivedKey, DecryptError> {
+        match self.keys.iter().position(|key| key.matches(encrypted)) {
+            Some(i) => {
+                let key = self.keys.remove(i).unwrap();
+                self.keys.push_back(key);
+            }
+            None => {
+                let key = DerivedKey::unlock_with_limits(encrypted, &self.password, &self.limits)?;
+                if self.keys.len() == self.capacity {
+                    self.keys.pop_front();
+                }
+                self.keys.push_back(key);
+            }
+        }
+        Ok(self.keys.back().unwrap())
+    }
+
+    /// Number of cached keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether no keys are cached
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl fmt::Debug for KeyCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyCache")
+            .field("capacity", &self.capacity)
+            .field("limits", &self.limits)
+            .field("keys", &self.keys)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BytesEncrypter, Config, DecryptError, DerivedKey, Encryptable, KdfParams, KeyCache,
+        SecretBytes,
+    };
+
+    #[test]
+    fn derived_key() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let key = DerivedKey::new("password", &config).unwrap();
+
+        let a = BytesEncrypter::encrypt_with_key(&SecretBytes::from(b"a"), &key).unwrap();
+        let b = BytesEncrypter::encrypt_with_key(&SecretBytes::from(b"b"), &key).unwrap();
+        assert_eq!(a.salt, b.salt);
+        assert_ne!(a.nonce, b.nonce);
+
+        assert_eq!(&*BytesEncrypter::decrypt_with_key(&a, &key).unwrap(), b"a");
+        assert_eq!(&*BytesEncrypter::decrypt(&b, "password").unwrap(), b"b");
+
+        let unlocked = DerivedKey::unlock(&a, "password").unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_key(&b, &unlocked).unwrap(),
+            b"b"
+        );
+
+        let other = DerivedKey::new("password", &config).unwrap();
+        assert!(matches!(
+            BytesEncrypter::decrypt_with_key(&a, &other),
+            Err(DecryptError::KeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn key_cache() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let keys = [
+            DerivedKey::new("password", &config).unwrap(),
+            DerivedKey::new("password", &config).unwrap(),
+            DerivedKey::new("password", &config).unwrap(),
+        ];
+        let encrypted: Vec<_> = keys
+            .iter()
+            .map(|key| BytesEncrypter::encrypt_with_key(&SecretBytes::from(b"test"), key).unwrap())
+            .collect();
+
+        let mut cache = KeyCache::new("password", 2);
+        for encrypted in encrypted.iter().chain(&encrypted) {
+            let key = cache.key(encrypted).unwrap();
+            assert_eq!(
+                &*BytesEncrypter::decrypt_with_key(encrypted, key).unwrap(),
+                b"test"
+            );
+        }
+        assert_eq!(cache.len(), 2);
+    }
+}
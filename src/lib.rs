@@ -1,88 +1,910 @@
-use base64::{engine::general_purpose, Engine};
-#[cfg(feature = "bincode")]
-use bincode::{Decode, Encode};
-use fernet::Fernet;
-use pbkdf2::pbkdf2_hmac;
-use rand::{thread_rng, Rng};
+#[cfg(feature = "age")]
+mod age;
+mod armor;
+#[cfg(feature = "tokio")]
+mod async_io;
 #[cfg(feature = "serde")]
-use serde::{Deserialize, Serialize};
-use sha2::Sha512;
-
-/// Represents the encrypted form of some bytes. Contains the salt and the data.
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
-pub struct Encrypted {
-    salt: [u8; 16],
-    data: String,
-}
+mod bytes;
+mod cipher;
+mod compression;
+mod config;
+mod encrypted;
+mod error;
+#[cfg(feature = "serde")]
+mod format;
+mod kdf;
+mod key;
+mod padding;
+mod recipient;
+mod rekey;
+mod secret;
+mod slots;
+mod stream;
+
+use std::time::{Duration, SystemTime};
+
+use compression::DEFAULT_MAX_DECOMPRESSED_LEN;
 
-/// `impl Encryptable<T>` struct that can be used to encrypt and decrypt a `Vec<u8>` to an `Encrypted`
-/// To encrypt arbitrary structs you can use the `bincode` library to first convert the struct to
-/// bytes. You then can encrypt the serialized bytes to an `Encrypted`.
+#[cfg(feature = "tokio")]
+pub use async_io::{AsyncDecryptReader, AsyncEncryptWriter};
+pub use cipher::Cipher;
+pub use compression::Compression;
+pub use config::Config;
+#[cfg(feature = "derive")]
+pub use encryptable_derive::{EncryptFields, Encryptable};
+pub use encrypted::Encrypted;
+pub use error::{BoxError, DecryptError, EncryptError, RekeyError};
+#[cfg(all(feature = "serde", feature = "bincode"))]
+pub use format::Bincode;
+#[cfg(feature = "cbor")]
+pub use format::Cbor;
+#[cfg(feature = "msgpack")]
+pub use format::MessagePack;
+#[cfg(feature = "serde")]
+pub use format::{Format, FormatEncrypter, Json, SerdeEncrypter};
+pub use kdf::{KdfAlgorithm, KdfLimits, KdfParams};
+pub use key::{DerivedKey, KeyCache};
+pub use padding::Padding;
+pub use recipient::{PublicKey, RecipientEncrypter, SecretKey};
+pub use rekey::RekeyReport;
+pub use secret::SecretBytes;
+pub use slots::KeySlot;
+pub use stream::{DecryptReader, EncryptWriter};
+
+/// `impl Encryptable<T>` struct that can be used to encrypt and decrypt `SecretBytes` to an `Encrypted`
+/// To encrypt arbitrary structs enable the `serde` feature and use `SerdeEncrypter`, which
+/// serializes the struct to bytes before encrypting it.
 ///
 /// This is a zero size struct
 ///
 /// # Encryption
 /// This by default uses:
-/// - **Algorithim**: Fernet ( 32 bit Base64 encoded key )
-/// - **Key**: Sha512 480,000 round PBKDF2 HMAC
-/// - **Salt**: 16 bit salt
+/// - **Algorithim**: XChaCha20-Poly1305
+/// - **Key**: Argon2id, 19 MiB, 2 iterations, 1 lane
+/// - **Salt**: 16 byte salt
+///
+/// The cipher and key derivation can be changed with [`Config::cipher`], [`Config::kdf`] and
+/// [`Encryptable::encrypt_with`]. Fernet, AES-256-GCM, ChaCha20-Poly1305 and age files are also
+/// supported.
+/// PBKDF2 HMAC Sha512 and scrypt are also supported, data encrypted with any of them can always
+/// be decrypted since the parameters are stored in the `Encrypted`
 ///
 /// # Speed
-/// This is **VERY** slow on debug builds (`~17s`). In release mode it happens almost instantly (`~0.55s`)
+/// Key derivation is slow on purpose, and a lot slower on debug builds than in release mode. Use a
+/// [`DerivedKey`] or [`KeyCache`] to only derive the key once when working with many items
 pub struct BytesEncrypter;
 
 /// This trait adds encrypt and decrypt functions to a type, `T`
+///
+/// Implementors only describe how `T` is turned into bytes and back, the encryption itself is
+/// shared by every implementation.
+///
+/// The returned [`Encrypted<T>`] can only be passed back to an `Encryptable<T>`, and an
+/// implementation with a [`Encryptable::type_tag`] only decrypts data it encrypted itself.
 pub trait Encryptable<T> {
-    /// Encrypts `T` with password and returns an result containing an `Encrypted` with the data and salt
-    fn encrypt(data: &T, password: &str) -> Result<Encrypted, Box<dyn std::error::Error>>;
-    /// Decrypts `Encrypted` with password and returns a result containing `T`
-    fn decrypt(data: &Encrypted, password: &str) -> Result<T, Box<dyn std::error::Error>>;
-}
+    /// Converts `T` to the bytes that get encrypted
+    fn to_plaintext(data: &T) -> Result<SecretBytes, EncryptError>;
+    /// Converts decrypted bytes back to `T`
+    fn from_plaintext(plaintext: SecretBytes) -> Result<T, DecryptError>;
 
-impl Encryptable<Vec<u8>> for BytesEncrypter {
-    fn encrypt(data: &Vec<u8>, password: &str) -> Result<Encrypted, Box<dyn std::error::Error>> {
-        let mut salt = [0u8; 16];
-        thread_rng().fill(&mut salt);
+    /// Config used by [`Encryptable::encrypt`], the default `Config` unless overridden
+    fn config() -> Config {
+        Config::default()
+    }
 
-        let mut kdf = [0u8; 32];
-        pbkdf2_hmac::<Sha512>(&password.as_bytes(), &salt, 480_000, &mut kdf);
+    /// Tag stored in the encrypted data and checked before decrypting, `None` unless overridden.
+    /// Decrypting fails with [`DecryptError::TypeMismatch`] if the tags are not equal, so
+    /// another implementation can not decrypt the data as a different type. Change the tag when
+    /// the plaintext format of `T` changes incompatibly. Fernet and age can not store a tag,
+    /// encrypting with them fails with [`EncryptError::Cipher`] if this returns `Some`
+    fn type_tag() -> Option<&'static str> {
+        None
+    }
 
-        let key = general_purpose::URL_SAFE.encode(&kdf);
-        let f = Fernet::new(&key.as_str()).unwrap();
-        Ok(Encrypted {
-            salt,
-            data: f.encrypt(&data),
-        })
+    /// Most bytes compressed data may decompress to, 64 MiB unless overridden. Decrypting
+    /// fails with [`DecryptError::TooLarge`] past it, so a small input can not exhaust memory
+    fn max_decompressed_len() -> usize {
+        DEFAULT_MAX_DECOMPRESSED_LEN
     }
 
-    fn decrypt(data: &Encrypted, password: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-        let mut kdf = [0u8; 32];
-        pbkdf2_hmac::<Sha512>(&password.as_bytes(), &data.salt, 480_000, &mut kdf);
+    /// Highest kdf costs accepted when decrypting with a password, the default [`KdfLimits`]
+    /// unless overridden
+    fn kdf_limits() -> KdfLimits {
+        KdfLimits::default()
+    }
 
-        let key = general_purpose::URL_SAFE.encode(&kdf);
-        let f = Fernet::new(&key.as_str()).unwrap();
-        Ok(f.decrypt(&data.data)?)
+    /// Encrypts `T` with password using [`Encryptable::config`]
+    fn encrypt(data: &T, password: &str) -> Result<Encrypted<T>, EncryptError> {
+        Self::encrypt_with(data, password, &Self::config())
+    }
+    /// Encrypts `T` with password and returns an result containing an `Encrypted` with the data and salt
+    fn encrypt_with(
+        data: &T,
+        password: &str,
+        config: &Config,
+    ) -> Result<Encrypted<T>, EncryptError> {
+        Self::encrypt_with_aad(data, password, config, &[])
+    }
+    /// Encrypts `T` with password, binding it to `aad`. The associated data is authenticated but
+    /// not stored, the same bytes have to be passed to [`Encryptable::decrypt_with_aad`].
+    ///
+    /// Fernet and age do not support associated data, so this fails if `aad` is not empty and the
+    /// config uses [`Cipher::Fernet`] or [`Cipher::Age`]
+    fn encrypt_with_aad(
+        data: &T,
+        password: &str,
+        config: &Config,
+        aad: &[u8],
+    ) -> Result<Encrypted<T>, EncryptError> {
+        DerivedKey::new(password, config)?.seal(&Self::to_plaintext(data)?, Self::type_tag(), aad)
+    }
+    /// Encrypts `T` with a random key that any of `passwords` can unlock, see
+    /// [`DerivedKey::with_key_slots`]. Passwords can be added, removed and changed afterwards
+    /// without re-encrypting the data
+    fn encrypt_with_key_slots(
+        data: &T,
+        passwords: &[&str],
+        config: &Config,
+    ) -> Result<Encrypted<T>, EncryptError> {
+        DerivedKey::with_key_slots(passwords, config)?.seal(
+            &Self::to_plaintext(data)?,
+            Self::type_tag(),
+            &[],
+        )
+    }
+    /// Encrypts `T` to the public keys of `recipients` with the cipher of `config`, see
+    /// [`RecipientEncrypter`]
+    fn encrypt_to_recipients(
+        data: &T,
+        recipients: &[PublicKey],
+        config: &Config,
+    ) -> Result<Encrypted<T>, EncryptError> {
+        DerivedKey::with_recipients(recipients, config)?.seal(
+            &Self::to_plaintext(data)?,
+            Self::type_tag(),
+            &[],
+        )
+    }
+    /// Encrypts `T` with an already derived key, skipping the kdf
+    fn encrypt_with_key(data: &T, key: &DerivedKey) -> Result<Encrypted<T>, EncryptError> {
+        Self::encrypt_with_key_and_aad(data, key, &[])
+    }
+    /// Encrypts `T` with an already derived key, binding it to `aad`, see
+    /// [`Encryptable::encrypt_with_aad`]
+    fn encrypt_with_key_and_aad(
+        data: &T,
+        key: &DerivedKey,
+        aad: &[u8],
+    ) -> Result<Encrypted<T>, EncryptError> {
+        key.seal(&Self::to_plaintext(data)?, Self::type_tag(), aad)
+    }
+    /// Decrypts `Encrypted` with password and returns a result containing `T`
+    fn decrypt(data: &Encrypted<T>, password: &str) -> Result<T, DecryptError> {
+        Self::decrypt_with_aad(data, password, &[])
+    }
+    /// Decrypts `Encrypted` with password, failing with [`DecryptError::Expired`] if it was
+    /// encrypted more than `ttl` ago, or with [`DecryptError::CreatedInFuture`] if its creation
+    /// time is more than a minute ahead of the clock. The creation time is authenticated along
+    /// with the data
+    fn decrypt_with_ttl(
+        data: &Encrypted<T>,
+        password: &str,
+        ttl: Duration,
+    ) -> Result<T, DecryptError> {
+        data.check_ttl(ttl, SystemTime::now())?;
+        Self::decrypt(data, password)
+    }
+    /// Decrypts `Encrypted` that was bound to `aad` by [`Encryptable::encrypt_with_aad`]. Fails
+    /// with [`DecryptError::Authentication`] if `aad` does not match
+    fn decrypt_with_aad(
+        data: &Encrypted<T>,
+        password: &str,
+        aad: &[u8],
+    ) -> Result<T, DecryptError> {
+        data.check_type_tag(Self::type_tag())?;
+        let key = DerivedKey::unlock_with_limits(data, password, &Self::kdf_limits())?;
+        Self::from_plaintext(key.open(data, aad, Self::max_decompressed_len())?)
+    }
+    /// Decrypts `Encrypted` that was encrypted to the public key of `secret_key` by
+    /// [`Encryptable::encrypt_to_recipients`]
+    fn decrypt_with_secret_key(
+        data: &Encrypted<T>,
+        secret_key: &SecretKey,
+    ) -> Result<T, DecryptError> {
+        data.check_type_tag(Self::type_tag())?;
+        let key = DerivedKey::unlock_with_secret_key(data, secret_key)?;
+        Self::from_plaintext(key.open(data, &[], Self::max_decompressed_len())?)
+    }
+    /// Decrypts `Encrypted` with an already derived key, skipping the kdf. Fails with
+    /// [`DecryptError::KeyMismatch`] if the key was derived with another salt or kdf
+    fn decrypt_with_key(data: &Encrypted<T>, key: &DerivedKey) -> Result<T, DecryptError> {
+        Self::decrypt_with_key_and_aad(data, key, &[])
+    }
+    /// Decrypts `Encrypted` with an already derived key, checking `aad`, see
+    /// [`Encryptable::decrypt_with_aad`]
+    fn decrypt_with_key_and_aad(
+        data: &Encrypted<T>,
+        key: &DerivedKey,
+        aad: &[u8],
+    ) -> Result<T, DecryptError> {
+        data.check_type_tag(Self::type_tag())?;
+        Self::from_plaintext(key.open(data, aad, Self::max_decompressed_len())?)
+    }
+    /// Changes the password of `data` that was bound to `aad`, re-encrypting it with `config`
+    /// and binding it to the same `aad`. Like [`Encrypted::rekey_with`], but using
+    /// [`Encryptable::max_decompressed_len`] and [`Encryptable::kdf_limits`]
+    fn rekey_with_aad(
+        data: &Encrypted<T>,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+        aad: &[u8],
+    ) -> Result<Encrypted<T>, RekeyError> {
+        data.rekey_aad(
+            old_password,
+            new_password,
+            config,
+            aad,
+            Self::max_decompressed_len(),
+            &Self::kdf_limits(),
+        )
+    }
+    /// Changes the password of every item, each paired with the associated data it was bound
+    /// to. Like [`Encrypted::rekey_all_with`], but using [`Encryptable::max_decompressed_len`]
+    /// and [`Encryptable::kdf_limits`]
+    fn rekey_all_with_aad<'a>(
+        items: impl IntoIterator<Item = (&'a Encrypted<T>, &'a [u8])>,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+    ) -> Result<RekeyReport<T>, EncryptError>
+    where
+        T: 'a,
+    {
+        Encrypted::rekey_all_aad(
+            items,
+            old_password,
+            new_password,
+            config,
+            Self::max_decompressed_len(),
+            Self::kdf_limits(),
+        )
+    }
+    /// Like [`Encryptable::encrypt`], but runs the kdf on tokio's blocking thread pool
+    #[cfg(feature = "tokio")]
+    fn encrypt_async(
+        data: &T,
+        password: &str,
+    ) -> impl std::future::Future<Output = Result<Encrypted<T>, EncryptError>> + Send
+    where
+        T: Sync,
+    {
+        async move { Self::encrypt_with_async(data, password, &Self::config()).await }
+    }
+    /// Like [`Encryptable::encrypt_with`], but runs the kdf on tokio's blocking thread pool
+    #[cfg(feature = "tokio")]
+    fn encrypt_with_async(
+        data: &T,
+        password: &str,
+        config: &Config,
+    ) -> impl std::future::Future<Output = Result<Encrypted<T>, EncryptError>> + Send
+    where
+        T: Sync,
+    {
+        async move { Self::encrypt_with_key(data, &DerivedKey::new_async(password, config).await?) }
+    }
+    /// Like [`Encryptable::decrypt`], but runs the kdf on tokio's blocking thread pool
+    #[cfg(feature = "tokio")]
+    fn decrypt_async(
+        data: &Encrypted<T>,
+        password: &str,
+    ) -> impl std::future::Future<Output = Result<T, DecryptError>> + Send {
+        async move {
+            data.check_type_tag(Self::type_tag())?;
+            let key = DerivedKey::unlock_async_with_limits(data, password, Self::kdf_limits());
+            Self::decrypt_with_key(data, &key.await?)
+        }
+    }
+}
+
+impl Encryptable<SecretBytes> for BytesEncrypter {
+    fn to_plaintext(data: &SecretBytes) -> Result<SecretBytes, EncryptError> {
+        Ok(data.clone())
+    }
+
+    fn from_plaintext(plaintext: SecretBytes) -> Result<SecretBytes, DecryptError> {
+        Ok(plaintext)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{BytesEncrypter, Encryptable};
+    use std::{
+        marker::PhantomData,
+        time::{Duration, SystemTime},
+    };
 
-    /// This is **VERY** slow on debug builds (`~17s`). In release mode it happens almost instantly (`~0.55s`)
+    use crate::{
+        BytesEncrypter, Cipher, Compression, Config, DecryptError, DerivedKey, EncryptError,
+        Encryptable, Encrypted, KdfLimits, KdfParams, Padding, SecretBytes,
+    };
+    use base64::{engine::general_purpose, Engine};
+
+    /// Uses the default kdf, so this is slow on debug builds
     #[test]
     fn encryption() {
         const CORRECT_PASSWORD: &str = "password";
         const INCORRECT_PASSWORD: &str = "incorrect password";
         const TEST_DATA: &[u8] = b"test";
 
-        let encrypted = BytesEncrypter::encrypt(&TEST_DATA.to_vec(), CORRECT_PASSWORD).unwrap();
+        let encrypted =
+            BytesEncrypter::encrypt(&SecretBytes::from(TEST_DATA), CORRECT_PASSWORD).unwrap();
         let d1 = BytesEncrypter::decrypt(&encrypted, CORRECT_PASSWORD);
         let d2 = BytesEncrypter::decrypt(&encrypted, INCORRECT_PASSWORD);
 
         assert!(&d1.is_ok());
-        assert!(&d2.is_err());
-        assert_eq!(&d1.unwrap(), TEST_DATA);
+        assert!(matches!(d2, Err(DecryptError::WrongPassword)));
+        assert_eq!(&*d1.unwrap(), TEST_DATA);
+    }
+
+    #[test]
+    fn malformed_token() {
+        let bad_base64 = Encrypted {
+            cipher: Cipher::Fernet,
+            kdf: KdfParams::default(),
+            salt: vec![0u8; 16],
+            nonce: Vec::new(),
+            data: b"not a token!".to_vec(),
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        };
+        let bad_version = Encrypted {
+            cipher: Cipher::Fernet,
+            kdf: KdfParams::default(),
+            salt: vec![0u8; 16],
+            nonce: Vec::new(),
+            data: general_purpose::URL_SAFE.encode([0x81; 73]).into_bytes(),
+            slots: Vec::new(),
+            type_tag: None,
+            key_check: Vec::new(),
+            created_at: None,
+            expires_at: None,
+            compression: None,
+            padding: None,
+            _type: PhantomData,
+        };
+
+        assert!(matches!(
+            BytesEncrypter::decrypt(&bad_base64, "password"),
+            Err(DecryptError::Malformed(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::decrypt(&bad_version, "password"),
+            Err(DecryptError::UnsupportedVersion(0x81))
+        ));
+    }
+
+    #[test]
+    fn fernet_token_is_stored_decoded() {
+        let config = Config::new()
+            .cipher(Cipher::Fernet)
+            .kdf(KdfParams::pbkdf2(1_000));
+        let mut encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config).unwrap();
+        assert_eq!(encrypted.data[0], 0x80);
+        assert_eq!(encrypted.data.len(), 1 + 8 + 16 + 16 + 32);
+
+        // Older data holds the base64 token
+        encrypted.data = general_purpose::URL_SAFE
+            .encode(&encrypted.data)
+            .into_bytes();
+        assert_eq!(
+            &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            b"test"
+        );
+    }
+
+    /// Stores a type tag, so it only decrypts its own data
+    struct TaggedEncrypter;
+
+    impl Encryptable<SecretBytes> for TaggedEncrypter {
+        fn to_plaintext(data: &SecretBytes) -> Result<SecretBytes, EncryptError> {
+            Ok(data.clone())
+        }
+
+        fn from_plaintext(plaintext: SecretBytes) -> Result<SecretBytes, DecryptError> {
+            Ok(plaintext)
+        }
+
+        fn type_tag() -> Option<&'static str> {
+            Some("tagged")
+        }
+    }
+
+    #[test]
+    fn type_tag() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let tagged =
+            TaggedEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config)
+                .unwrap();
+        assert_eq!(tagged.type_tag(), Some("tagged"));
+
+        let tagged = Encrypted::from_bytes(&tagged.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            &*TaggedEncrypter::decrypt(&tagged, "password").unwrap(),
+            b"test"
+        );
+        assert!(matches!(
+            BytesEncrypter::decrypt(&tagged, "password"),
+            Err(DecryptError::TypeMismatch)
+        ));
+
+        let untagged =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config).unwrap();
+        assert!(matches!(
+            TaggedEncrypter::decrypt(&untagged, "password"),
+            Err(DecryptError::TypeMismatch)
+        ));
+
+        // The tag is authenticated, removing it breaks the data
+        let mut stripped = tagged.clone();
+        stripped.type_tag = None;
+        assert!(matches!(
+            BytesEncrypter::decrypt(&stripped, "password"),
+            Err(DecryptError::Tampered)
+        ));
+
+        // Fernet can not authenticate a tag, so it can not store one
+        let fernet = config.clone().cipher(Cipher::Fernet);
+        assert!(matches!(
+            TaggedEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &fernet),
+            Err(EncryptError::Cipher(_))
+        ));
+        let untagged =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &fernet).unwrap();
+        let mut added = untagged.clone();
+        added.type_tag = Some("tagged".to_string());
+        assert!(matches!(
+            TaggedEncrypter::decrypt(&added, "password"),
+            Err(DecryptError::Malformed(_))
+        ));
+        let mut added = [untagged.clone(), untagged];
+        added[0].compression = Some(Compression::Zstd);
+        added[1].padding = Some(Padding::PowerOfTwo);
+        for encrypted in added {
+            assert!(matches!(
+                BytesEncrypter::decrypt(&encrypted, "password"),
+                Err(DecryptError::Malformed(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn key_check() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config).unwrap();
+        assert_eq!(encrypted.key_check.len(), 32);
+
+        let mut tampered = encrypted.clone();
+        *tampered.data.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            BytesEncrypter::decrypt(&tampered, "password"),
+            Err(DecryptError::Tampered)
+        ));
+        assert!(matches!(
+            BytesEncrypter::decrypt(&tampered, "incorrect password"),
+            Err(DecryptError::WrongPassword)
+        ));
+
+        // Older data without a key check still decrypts, but can not tell the errors apart
+        let mut unchecked = encrypted.clone();
+        unchecked.key_check.clear();
+        let unchecked = Encrypted::from_bytes(&unchecked.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt(&unchecked, "password").unwrap(),
+            b"test"
+        );
+        assert!(matches!(
+            BytesEncrypter::decrypt(&unchecked, "incorrect password"),
+            Err(DecryptError::Authentication)
+        ));
+    }
+
+    #[test]
+    fn expiry() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config).unwrap();
+        let created_at = encrypted.created_at().unwrap();
+        assert!(SystemTime::now().duration_since(created_at).unwrap() < Duration::from_secs(5));
+        assert_eq!(encrypted.expires_at(), None);
+
+        let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+        let hour = Duration::from_secs(3_600);
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_ttl(&encrypted, "password", hour).unwrap(),
+            b"test"
+        );
+        assert!(matches!(
+            encrypted.check_ttl(hour, created_at + 2 * hour),
+            Err(DecryptError::Expired)
+        ));
+        assert!(matches!(
+            encrypted.check_ttl(hour, created_at - hour),
+            Err(DecryptError::CreatedInFuture)
+        ));
+        assert!(encrypted
+            .check_ttl(hour, created_at - Duration::from_secs(30))
+            .is_ok());
+
+        // The creation time is authenticated
+        let mut changed = encrypted.clone();
+        changed.created_at = Some(changed.created_at.unwrap() - 60);
+        assert!(matches!(
+            BytesEncrypter::decrypt_with_ttl(&changed, "password", hour),
+            Err(DecryptError::Tampered)
+        ));
+
+        let fernet = config.clone().cipher(Cipher::Fernet);
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &fernet).unwrap();
+        assert_eq!(encrypted.created_at, None);
+        assert!(encrypted.created_at().is_some());
+        assert!(BytesEncrypter::decrypt_with_ttl(&encrypted, "password", hour).is_ok());
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(
+                &SecretBytes::default(),
+                "password",
+                &fernet.expires_after(hour)
+            ),
+            Err(EncryptError::Cipher(_))
+        ));
+
+        let expiring = config.clone().expires_after(hour);
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &expiring)
+                .unwrap();
+        let created_at = encrypted.created_at().unwrap();
+        assert_eq!(encrypted.expires_at(), Some(created_at + hour));
+        assert!(BytesEncrypter::decrypt(&encrypted, "password").is_ok());
+        assert!(matches!(
+            encrypted.check_expiry(created_at + hour),
+            Err(DecryptError::Expired)
+        ));
+
+        // Times that do not fit in a SystemTime
+        let mut far = encrypted.clone();
+        far.expires_at = Some(u64::MAX);
+        assert!(matches!(
+            BytesEncrypter::decrypt(&far, "password"),
+            Err(DecryptError::Malformed(_))
+        ));
+        far.created_at = Some(u64::MAX);
+        assert!(matches!(
+            far.check_ttl(hour, SystemTime::now()),
+            Err(DecryptError::Malformed(_))
+        ));
+        assert!(encrypted
+            .check_ttl(Duration::MAX, SystemTime::now())
+            .is_ok());
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(
+                &SecretBytes::default(),
+                "password",
+                &config.clone().expires_after(Duration::MAX)
+            ),
+            Err(EncryptError::Serialization(_))
+        ));
+
+        let expired = config.expires_after(Duration::ZERO);
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &expired)
+                .unwrap();
+        assert!(matches!(
+            BytesEncrypter::decrypt(&encrypted, "password"),
+            Err(DecryptError::Expired)
+        ));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compression() {
+        use crate::Compression;
+
+        let config = Config::new()
+            .kdf(KdfParams::pbkdf2(1_000))
+            .compression(Compression::Zstd);
+        let data = SecretBytes::new(br#"{"id":1,"tags":["a","b"]}"#.repeat(100));
+        let encrypted = BytesEncrypter::encrypt_with(&data, "password", &config).unwrap();
+        assert_eq!(encrypted.compression, Some(Compression::Zstd));
+        assert!(encrypted.data.len() < data.len() / 5);
+
+        let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+        assert_eq!(
+            BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            data
+        );
+
+        // The flag is authenticated
+        let mut changed = encrypted.clone();
+        changed.compression = None;
+        assert!(matches!(
+            BytesEncrypter::decrypt(&changed, "password"),
+            Err(DecryptError::Tampered)
+        ));
+
+        // Data that does not compress is stored as it is
+        let random = SecretBytes::new((0..=255).collect());
+        let encrypted = BytesEncrypter::encrypt_with(&random, "password", &config).unwrap();
+        assert_eq!(encrypted.compression, None);
+        assert_eq!(
+            BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            random
+        );
+
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(&data, "password", &config.cipher(Cipher::Fernet)),
+            Err(EncryptError::Cipher(_))
+        ));
+    }
+
+    #[test]
+    fn padding() {
+        use crate::Padding;
+
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let lens = |padding| {
+            let config = config.clone().padding(padding);
+            [10, 11, 12, 100, 101].map(|len| {
+                let data = SecretBytes::new(vec![7; len]);
+                let encrypted = BytesEncrypter::encrypt_with(&data, "password", &config).unwrap();
+                assert_eq!(encrypted.padding, Some(padding));
+                let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+                assert_eq!(
+                    BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+                    data
+                );
+                encrypted.data.len()
+            })
+        };
+        // 16 bytes of tag on top of the padded length
+        assert_eq!(lens(Padding::Padme), [28, 28, 30, 120, 120]);
+        assert_eq!(lens(Padding::PowerOfTwo), [32, 32, 32, 144, 144]);
+        assert_eq!(lens(Padding::Block(64)), [80, 80, 80, 144, 144]);
+
+        // The scheme is authenticated
+        let data = SecretBytes::from("data");
+        let encrypted = BytesEncrypter::encrypt_with(
+            &data,
+            "password",
+            &config.clone().padding(Padding::Padme),
+        )
+        .unwrap();
+        let mut changed = encrypted.clone();
+        changed.padding = None;
+        assert!(matches!(
+            BytesEncrypter::decrypt(&changed, "password"),
+            Err(DecryptError::Tampered)
+        ));
+
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(
+                &data,
+                "password",
+                &config.clone().padding(Padding::Block(0))
+            ),
+            Err(EncryptError::Cipher(_))
+        ));
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(
+                &data,
+                "password",
+                &config.cipher(Cipher::Fernet).padding(Padding::Padme)
+            ),
+            Err(EncryptError::Cipher(_))
+        ));
+    }
+
+    /// Written by 0.1.1, which only stored the salt and the Fernet token
+    #[cfg(feature = "serde")]
+    #[test]
+    fn baseline_serde() {
+        const BASELINE: &str = r#"{"salt":[13,109,150,113,70,34,190,3,2,112,254,53,104,120,210,42],"data":"gAAAAABq0_4HCO7Ll0R8j-6OPUgYyX9TN120_Fd7FKcXGKihA0T700e2jA23oleJjbZ_VRYzjt5sPq1MEc4RifMNNZjpc1hVBg=="}"#;
+
+        let encrypted: Encrypted = serde_json::from_str(BASELINE).unwrap();
+        assert_eq!(encrypted.cipher(), Cipher::Fernet);
+        assert_eq!(encrypted.kdf(), &KdfParams::pbkdf2(480_000));
+        assert_eq!(
+            BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            SecretBytes::from("test")
+        );
+    }
+
+    /// Written by 0.1.1 with the `bincode` feature, which encoded the salt and the Fernet token
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn baseline_bincode() {
+        const BASELINE: &[u8] = b"\x0d\x6d\x96\x71\x46\x22\xbe\x03\x02\x70\xfe\x35\x68\x78\xd2\x2a\x64\
+            gAAAAABq0_4HCO7Ll0R8j-6OPUgYyX9TN120_Fd7FKcXGKihA0T700e2jA23oleJjbZ_VRYzjt5sPq1MEc4RifMNNZjpc1hVBg==";
+
+        let (encrypted, _): (Encrypted, _) =
+            bincode::decode_from_slice(BASELINE, bincode::config::standard()).unwrap();
+        assert_eq!(encrypted.cipher(), Cipher::Fernet);
+        assert_eq!(encrypted.kdf(), &KdfParams::pbkdf2(480_000));
+        assert_eq!(
+            BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            SecretBytes::from("test")
+        );
+    }
+
+    #[test]
+    fn kdf_params_are_read_from_encrypted() {
+        let config = Config::new().cipher(Cipher::Fernet).kdf(KdfParams {
+            salt_len: 32,
+            ..KdfParams::pbkdf2(1_000)
+        });
+
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config).unwrap();
+        assert_eq!(encrypted.kdf, config.kdf);
+        assert_eq!(encrypted.salt.len(), 32);
+        assert_eq!(
+            &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            b"test"
+        );
+
+        let bad_output_len = Config::new().kdf(KdfParams {
+            output_len: 16,
+            ..KdfParams::pbkdf2(1_000)
+        });
+        assert!(
+            BytesEncrypter::encrypt_with(&SecretBytes::default(), "password", &bad_output_len)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn kdf_limits() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from("test"), "password", &config).unwrap();
+
+        // Rejected before any memory is allocated or time is spent
+        for kdf in [
+            KdfParams::pbkdf2(u32::MAX),
+            KdfParams::argon2id(u32::MAX, 2, 1),
+            KdfParams::argon2id(19 * 1024, u32::MAX, 1),
+            KdfParams::argon2id(19 * 1024, 2, u32::MAX),
+            KdfParams::scrypt(63, 8, 1),
+            KdfParams::scrypt(20, u32::MAX, 1),
+        ] {
+            let mut changed = encrypted.clone();
+            changed.kdf = kdf;
+            assert!(matches!(
+                BytesEncrypter::decrypt(&changed, "password"),
+                Err(DecryptError::Malformed(_))
+            ));
+        }
+
+        let limits = KdfLimits::new().max_iterations(500);
+        assert!(matches!(
+            DerivedKey::unlock_with_limits(&encrypted, "password", &limits),
+            Err(DecryptError::Malformed(_))
+        ));
+        let slots = BytesEncrypter::encrypt_with_key_slots(
+            &SecretBytes::from("test"),
+            &["password"],
+            &config,
+        )
+        .unwrap();
+        assert!(matches!(
+            DerivedKey::unlock_with_limits(&slots, "password", &limits),
+            Err(DecryptError::Malformed(_))
+        ));
+        assert!(DerivedKey::unlock_with_limits(&slots, "password", &KdfLimits::new()).is_ok());
+    }
+
+    #[test]
+    fn memory_hard_kdfs() {
+        for kdf in [KdfParams::argon2id(64, 1, 1), KdfParams::scrypt(4, 8, 1)] {
+            let encrypted = BytesEncrypter::encrypt_with(
+                &SecretBytes::from(b"test"),
+                "password",
+                &Config::new().kdf(kdf),
+            )
+            .unwrap();
+
+            assert_eq!(encrypted.kdf, kdf);
+            let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+            assert_eq!(
+                &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+                b"test"
+            );
+            assert!(matches!(
+                BytesEncrypter::decrypt(&encrypted, "incorrect password"),
+                Err(DecryptError::WrongPassword)
+            ));
+        }
+
+        let invalid = Config::new().kdf(KdfParams::argon2id(0, 0, 0));
+        assert!(matches!(
+            BytesEncrypter::encrypt_with(&SecretBytes::default(), "password", &invalid),
+            Err(EncryptError::Kdf(_))
+        ));
+    }
+
+    #[test]
+    fn ciphers() {
+        for cipher in [
+            Cipher::Fernet,
+            Cipher::Aes256Gcm,
+            Cipher::ChaCha20Poly1305,
+            Cipher::XChaCha20Poly1305,
+        ] {
+            let config = Config::new().cipher(cipher).kdf(KdfParams::pbkdf2(1_000));
+            let encrypted =
+                BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "password", &config)
+                    .unwrap();
+
+            assert_eq!(encrypted.cipher, cipher);
+            assert_eq!(encrypted.nonce.len(), cipher.nonce_len());
+            let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+            assert_eq!(
+                &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+                b"test"
+            );
+
+            let mut tampered = encrypted.clone();
+            *tampered.data.last_mut().unwrap() ^= 1;
+            assert!(BytesEncrypter::decrypt(&tampered, "password").is_err());
+        }
+    }
+
+    #[test]
+    fn associated_data() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted = BytesEncrypter::encrypt_with_aad(
+            &SecretBytes::from(b"test"),
+            "password",
+            &config,
+            b"row 1",
+        )
+        .unwrap();
+
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_aad(&encrypted, "password", b"row 1").unwrap(),
+            b"test"
+        );
+        assert!(matches!(
+            BytesEncrypter::decrypt_with_aad(&encrypted, "password", b"row 2"),
+            Err(DecryptError::Tampered)
+        ));
+        assert!(matches!(
+            BytesEncrypter::decrypt(&encrypted, "password"),
+            Err(DecryptError::Tampered)
+        ));
+
+        let fernet = config.cipher(Cipher::Fernet);
+        assert!(matches!(
+            BytesEncrypter::encrypt_with_aad(
+                &SecretBytes::default(),
+                "password",
+                &fernet,
+                b"row 1"
+            ),
+            Err(EncryptError::Cipher(_))
+        ));
+        // Key slots are bound to the key id, which Fernet can not authenticate
+        assert!(matches!(
+            BytesEncrypter::encrypt_with_key_slots(&SecretBytes::default(), &["password"], &fernet),
+            Err(EncryptError::Cipher(_))
+        ));
     }
 }
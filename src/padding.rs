@@ -0,0 +1,155 @@
+#[cfg(feature = "bincode")]
+use bincode::{Decode, Encode};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{DecryptError, SecretBytes};
+
+/// Byte written after the plaintext, followed by zeros up to the padded length
+const PADDING_MARKER: u8 = 0x80;
+
+/// Padding added to the plaintext before it is encrypted, set with
+/// [`Config::padding`](crate::Config::padding), so the length of the ciphertext does not give
+/// away the exact length of the plaintext. The scheme is stored in the `Encrypted` and the
+/// padding is removed when it is decrypted.
+///
+/// The plaintext is followed by a `0x80` byte and then zeros up to the padded length, so there
+/// is always at least one byte of padding. Padding is applied after compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+#[non_exhaustive]
+pub enum Padding {
+    /// PADMÉ, pads to a length with as many low bits cleared as the length has significant
+    /// bits. Adds at most 12% and hides the low bits of the length
+    Padme,
+    /// Pads to the next power of two, adds up to 100% but only leaks the magnitude of the length
+    PowerOfTwo,
+    /// Pads to a multiple of the block size in bytes, which must not be zero
+    Block(u32),
+}
+
+impl Padding {
+    /// Id of the scheme in the binary format
+    pub(crate) fn id(self) -> u8 {
+        match self {
+            Self::Padme => 1,
+            Self::PowerOfTwo => 2,
+            Self::Block(_) => 3,
+        }
+    }
+
+    /// Encodes the id, followed by the block size for [`Padding::Block`]
+    pub(crate) fn to_bytes(self) -> Vec<u8> {
+        let mut out = vec![self.id()];
+        if let Self::Block(size) = self {
+            out.extend_from_slice(&size.to_be_bytes());
+        }
+        out
+    }
+
+    /// Decodes the output of [`Padding::to_bytes`]
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, DecryptError> {
+        match bytes {
+            [1] => Ok(Self::Padme),
+            [2] => Ok(Self::PowerOfTwo),
+            [3, size @ ..] => {
+                let size = size
+                    .try_into()
+                    .map_err(|_| DecryptError::Malformed("padding field has the wrong length"))?;
+                Ok(Self::Block(u32::from_be_bytes(size)))
+            }
+            [id, ..] => Err(DecryptError::UnsupportedPadding(*id)),
+            [] => Err(DecryptError::Malformed("padding field is empty")),
+        }
+    }
+
+    /// Length `len` bytes are padded to, `None` if it does not fit in a `usize`
+    fn padded_len(self, len: usize) -> Option<usize> {
+        match self {
+            Self::Padme if len < 2 => Some(len),
+            Self::Padme => {
+                // Keep as many high bits of the length as it takes to write its bit length
+                let e = len.ilog2();
+                let s = e.ilog2() + 1;
+                let mask = (1usize << (e - s)) - 1;
+                len.checked_add(mask).map(|len| len & !mask)
+            }
+            Self::PowerOfTwo => len.checked_next_power_of_two(),
+            Self::Block(size) => len.checked_next_multiple_of(size as usize),
+        }
+    }
+
+    /// Checks the scheme can be used to encrypt
+    pub(crate) fn check(self) -> Result<(), &'static str> {
+        match self {
+            Self::Block(0) => Err("padding block size must not be zero"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Appends the marker and the padding to `data`
+    pub(crate) fn pad(self, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, &'static str> {
+        self.check()?;
+        let len = data
+            .len()
+            .checked_add(1)
+            .and_then(|len| self.padded_len(len))
+            .ok_or("plaintext is too long to pad")?;
+
+        let mut out = Zeroizing::new(Vec::with_capacity(len));
+        out.extend_from_slice(data);
+        out.push(PADDING_MARKER);
+        out.resize(len, 0);
+        Ok(out)
+    }
+}
+
+/// Removes the padding added by [`Padding::pad`]
+pub(crate) fn unpad(data: SecretBytes) -> Result<SecretBytes, DecryptError> {
+    match data.iter().rposition(|&b| b != 0) {
+        Some(end) if data[end] == PADDING_MARKER => Ok(SecretBytes::from(&data[..end])),
+        _ => Err(DecryptError::Malformed("padding is invalid")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{unpad, Padding};
+    use crate::{DecryptError, SecretBytes};
+
+    #[test]
+    fn padding() {
+        assert_eq!(Padding::Padme.padded_len(1), Some(1));
+        assert_eq!(Padding::Padme.padded_len(9), Some(10));
+        assert_eq!(Padding::Padme.padded_len(100), Some(104));
+        assert_eq!(Padding::Padme.padded_len(1000), Some(1024));
+        assert_eq!(Padding::PowerOfTwo.padded_len(100), Some(128));
+        assert_eq!(Padding::Block(64).padded_len(100), Some(128));
+        assert_eq!(Padding::PowerOfTwo.padded_len(usize::MAX), None);
+
+        for padding in [Padding::Padme, Padding::PowerOfTwo, Padding::Block(16)] {
+            assert_eq!(Padding::from_bytes(&padding.to_bytes()).unwrap(), padding);
+            for len in [0, 1, 15, 16, 100, 1000] {
+                let data = vec![0u8; len];
+                let padded = padding.pad(&data).unwrap();
+                assert!(padded.len() > len);
+                assert_eq!(
+                    &*unpad(SecretBytes::new(padded.to_vec())).unwrap(),
+                    &data[..]
+                );
+            }
+        }
+
+        assert!(Padding::Block(0).pad(b"data").is_err());
+        assert!(matches!(
+            unpad(SecretBytes::from(&[1, 2, 0][..])),
+            Err(DecryptError::Malformed(_))
+        ));
+        assert!(matches!(
+            Padding::from_bytes(&[9]),
+            Err(DecryptError::UnsupportedPadding(9))
+        ));
+    }
+}
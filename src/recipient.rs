@@ -0,0 +1,311 @@
+use std::fmt;
+
+use hkdf::Hkdf;
+use rand::thread_rng;
+use sha2::Sha256;
+use x25519_dalek::StaticSecret;
+use zeroize::Zeroizing;
+
+use crate::{
+    slots::check_key_slot_count, BytesEncrypter, Cipher, Config, DecryptError, EncryptError,
+    Encryptable, Encrypted, KdfAlgorithm, KdfParams, KeySlot, RekeyError, SecretBytes,
+};
+
+/// HKDF info for the key wrapping a recipient's slot
+const HKDF_INFO: &[u8] = b"encryptable x25519";
+
+/// X25519 public key of a recipient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey([u8; 32]);
+
+impl PublicKey {
+    /// Creates a public key from its 32 bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// The 32 bytes of the key
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+/// X25519 secret key of a recipient. The key is wiped from memory when dropped and `Debug` does
+/// not print it
+#[derive(Clone)]
+pub struct SecretKey(StaticSecret);
+
+impl SecretKey {
+    /// Generates a random secret key
+    pub fn generate() -> Self {
+        Self(StaticSecret::random_from_rng(thread_rng()))
+    }
+
+    /// Creates a secret key from its 32 bytes
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self(StaticSecret::from(bytes))
+    }
+
+    /// The 32 bytes of the key
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        Zeroizing::new(self.0.to_bytes())
+    }
+
+    /// Public key to encrypt to this secret key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(x25519_dalek::PublicKey::from(&self.0).to_bytes())
+    }
+
+    /// X25519 shared secret with `public_key`, rejecting low order points
+    pub(crate) fn diffie_hellman(
+        &self,
+        public_key: &PublicKey,
+    ) -> Result<Zeroizing<[u8; 32]>, &'static str> {
+        let shared = self
+            .0
+            .diffie_hellman(&x25519_dalek::PublicKey::from(public_key.0));
+        if !shared.was_contributory() {
+            return Err("public key is a low order point");
+        }
+        Ok(Zeroizing::new(shared.to_bytes()))
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SecretKey")
+            .field(&self.public_key())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Encrypts `SecretBytes` to the public keys of one or more recipients, so no password has to be
+/// shared. Any of the matching secret keys can decrypt the data.
+///
+/// The data is encrypted with a random key, which is wrapped in a key slot for each recipient.
+/// The wrapping key is agreed with X25519 between a fresh ephemeral key and the recipient, and
+/// derived with HKDF Sha256. The result is a normal `Encrypted`, password key slots can be mixed
+/// in with [`Encrypted::add_key_slot_with_secret_key`].
+///
+/// Use [`Encryptable::encrypt_to_recipients`] and [`Encryptable::decrypt_with_secret_key`] to
+/// encrypt other types.
+///
+/// This is a zero size struct
+///
+/// ```
+/// use encryptable::{RecipientEncrypter, SecretBytes, SecretKey};
+///
+/// let secret_key = SecretKey::generate();
+/// let encrypted =
+///     RecipientEncrypter::encrypt(&SecretBytes::from("test"), &[secret_key.public_key()]).unwrap();
+/// let decrypted = RecipientEncrypter::decrypt(&encrypted, &secret_key).unwrap();
+/// assert_eq!(&*decrypted, b"test");
+/// ```
+pub struct RecipientEncrypter;
+
+impl RecipientEncrypter {
+    /// Encrypts `data` to `recipients` with the default `Config`
+    pub fn encrypt(
+        data: &SecretBytes,
+        recipients: &[PublicKey],
+    ) -> Result<Encrypted, EncryptError> {
+        Self::encrypt_with(data, recipients, &Config::default())
+    }
+
+    /// Encrypts `data` to `recipients` with the cipher of `config`
+    pub fn encrypt_with(
+        data: &SecretBytes,
+        recipients: &[PublicKey],
+        config: &Config,
+    ) -> Result<Encrypted, EncryptError> {
+        BytesEncrypter::encrypt_to_recipients(data, recipients, config)
+    }
+
+    /// Decrypts `data` with the secret key of one of its recipients
+    pub fn decrypt(data: &Encrypted, secret_key: &SecretKey) -> Result<SecretBytes, DecryptError> {
+        BytesEncrypter::decrypt_with_secret_key(data, secret_key)
+    }
+}
+
+impl<T> Encrypted<T> {
+    /// Adds a slot for `new_password` using `kdf`, unlocking the key with the secret key of one
+    /// of the recipients. Lets data encrypted only to recipients be decrypted with a password
+    /// too, see [`Encrypted::add_key_slot`] once it has one
+    pub fn add_key_slot_with_secret_key(
+        &mut self,
+        secret_key: &SecretKey,
+        new_password: &str,
+        kdf: KdfParams,
+    ) -> Result<(), RekeyError> {
+        if self.kdf.algorithm != KdfAlgorithm::KeySlots {
+            return Err(RekeyError::Decrypt(DecryptError::Malformed(
+                "data is not encrypted with key slots",
+            )));
+        }
+        check_key_slot_count(self.slots.len() + 1).map_err(RekeyError::Encrypt)?;
+
+        let key = open_recipient_slots(self.cipher, &self.salt, &self.slots, secret_key)
+            .map_err(RekeyError::Decrypt)?;
+        let slot = KeySlot::new(self.cipher, &self.salt, &key, new_password, kdf)
+            .map_err(RekeyError::Encrypt)?;
+        self.slots.push(slot);
+        Ok(())
+    }
+}
+
+impl KeySlot {
+    /// Wraps `key` for `recipient` with a fresh ephemeral key and nonce
+    pub(crate) fn for_recipient(
+        cipher: Cipher,
+        key_id: &[u8],
+        key: &[u8],
+        recipient: &PublicKey,
+    ) -> Result<Self, EncryptError> {
+        let ephemeral = SecretKey::generate();
+        let ephemeral_public = ephemeral.public_key();
+        let wrapping_key = wrapping_key(&ephemeral, recipient, &ephemeral_public, recipient)
+            .map_err(|e| EncryptError::Kdf(e.into()))?;
+
+        let nonce = cipher.generate_nonce();
+        let key = cipher
+            .encrypt(&wrapping_key[..], &nonce, key_id, key)
+            .map_err(EncryptError::Cipher)?;
+
+        Ok(Self {
+            kdf: KdfParams::x25519(),
+            salt: ephemeral_public.0.to_vec(),
+            nonce,
+            key,
+        })
+    }
+}
+
+/// Finds the recipient slot `secret_key` unlocks and returns the unwrapped key
+pub(crate) fn open_recipient_slots(
+    cipher: Cipher,
+    key_id: &[u8],
+    slots: &[KeySlot],
+    secret_key: &SecretKey,
+) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+    let recipient = secret_key.public_key();
+    let recipient_slots = slots
+        .iter()
+        .filter(|slot| slot.kdf.algorithm == KdfAlgorithm::X25519);
+    for slot in recipient_slots {
+        let ephemeral = <[u8; 32]>::try_from(&slot.salt[..])
+            .map_err(|_| DecryptError::Malformed("ephemeral key has the wrong length"))?;
+        let ephemeral = PublicKey(ephemeral);
+        let Ok(wrapping_key) = wrapping_key(secret_key, &ephemeral, &ephemeral, &recipient) else {
+            continue;
+        };
+
+        match cipher.decrypt(&wrapping_key[..], &slot.nonce, key_id, &slot.key) {
+            Ok(key) => return Ok(Zeroizing::new(key.to_vec())),
+            Err(DecryptError::Authentication) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(DecryptError::Authentication)
+}
+
+/// Agrees on a key between `secret_key` and `public_key`, and derives the wrapping key of a slot
+/// from it. The ephemeral and recipient public keys are used as salt
+fn wrapping_key(
+    secret_key: &SecretKey,
+    public_key: &PublicKey,
+    ephemeral: &PublicKey,
+    recipient: &PublicKey,
+) -> Result<Zeroizing<[u8; 32]>, &'static str> {
+    let shared = secret_key.diffie_hellman(public_key)?;
+
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(&ephemeral.0);
+    salt[32..].copy_from_slice(&recipient.0);
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(Some(&salt), &*shared)
+        .expand(HKDF_INFO, key.as_mut())
+        .map_err(|_| "invalid hkdf output length")?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BytesEncrypter, Config, DecryptError, Encryptable, Encrypted, KdfParams, PublicKey,
+        RecipientEncrypter, RekeyError, SecretBytes, SecretKey,
+    };
+
+    #[test]
+    fn recipients() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let eve = SecretKey::generate();
+
+        let mut encrypted = RecipientEncrypter::encrypt(
+            &SecretBytes::from(b"test"),
+            &[alice.public_key(), bob.public_key()],
+        )
+        .unwrap();
+        assert_eq!(encrypted.key_slots().len(), 2);
+
+        let encrypted_bytes = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+        for secret_key in [&alice, &bob] {
+            assert_eq!(
+                &*RecipientEncrypter::decrypt(&encrypted_bytes, secret_key).unwrap(),
+                b"test"
+            );
+        }
+        assert!(matches!(
+            RecipientEncrypter::decrypt(&encrypted, &eve),
+            Err(DecryptError::Authentication)
+        ));
+        assert!(matches!(
+            BytesEncrypter::decrypt(&encrypted, "password"),
+            Err(DecryptError::WrongPassword)
+        ));
+
+        let kdf = KdfParams::pbkdf2(1_000);
+        assert!(matches!(
+            encrypted.add_key_slot_with_secret_key(&eve, "password", kdf),
+            Err(RekeyError::Decrypt(DecryptError::Authentication))
+        ));
+        encrypted
+            .add_key_slot_with_secret_key(&alice, "password", kdf)
+            .unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+            b"test"
+        );
+        assert!(encrypted.remove_key_slot(2).is_some());
+
+        assert!(encrypted.remove_key_slot(0).is_some());
+        assert!(RecipientEncrypter::decrypt(&encrypted, &alice).is_err());
+        assert_eq!(
+            &*RecipientEncrypter::decrypt(&encrypted, &bob).unwrap(),
+            b"test"
+        );
+
+        let password = BytesEncrypter::encrypt_with(
+            &SecretBytes::default(),
+            "password",
+            &Config::new().kdf(KdfParams::pbkdf2(1_000)),
+        )
+        .unwrap();
+        assert!(matches!(
+            RecipientEncrypter::decrypt(&password, &alice),
+            Err(DecryptError::KeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn secret_key() {
+        let secret_key = SecretKey::generate();
+        let restored = SecretKey::from_bytes(*secret_key.to_bytes());
+        assert_eq!(restored.public_key(), secret_key.public_key());
+        assert!(!format!("{secret_key:?}").contains(&format!("{:?}", *secret_key.to_bytes())));
+
+        let low_order = PublicKey::from_bytes([0; 32]);
+        assert!(RecipientEncrypter::encrypt(&SecretBytes::default(), &[low_order]).is_err());
+    }
+}
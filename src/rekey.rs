@@ -0,0 +1,320 @@
+use crate::{
+    compression::DEFAULT_MAX_DECOMPRESSED_LEN, Config, DerivedKey, EncryptError, Encrypted,
+    KdfLimits, KeyCache, RekeyError, SecretBytes,
+};
+
+/// Number of old keys kept around while rekeying a batch
+const REKEY_CACHE_SIZE: usize = 16;
+
+/// Result of [`Encrypted::rekey_all`]. Items are identified by their position in the input
+#[derive(Debug)]
+pub struct RekeyReport<T = SecretBytes> {
+    /// Items that were encrypted with the new password
+    pub rekeyed: Vec<(usize, Encrypted<T>)>,
+    /// Items that could not be rekeyed, these still need the old password
+    pub failed: Vec<(usize, RekeyError)>,
+}
+
+impl<T> Default for RekeyReport<T> {
+    fn default() -> Self {
+        Self {
+            rekeyed: Vec::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+impl<T> RekeyReport<T> {
+    /// Whether every item was rekeyed
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl<T> Encrypted<T> {
+    /// Changes the password, re-encrypting with a fresh salt and the default `Config`. The
+    /// plaintext never leaves this function and is wiped from memory afterwards. The type tag,
+    /// creation time and expiry time are kept, as are the compression and padding unless the
+    /// `Config` sets its own.
+    ///
+    /// Compressed data may decompress to at most 64 MiB and the kdf costs are checked against the
+    /// default [`KdfLimits`] here, use
+    /// [`Encryptable::rekey_with_aad`](crate::Encryptable::rekey_with_aad) for data with
+    /// associated data or the limits of an `Encryptable`
+    pub fn rekey(&self, old_password: &str, new_password: &str) -> Result<Self, RekeyError> {
+        self.rekey_with(old_password, new_password, &Config::default())
+    }
+
+    /// Changes the password, re-encrypting with a fresh salt and `config`
+    pub fn rekey_with(
+        &self,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+    ) -> Result<Self, RekeyError> {
+        self.rekey_aad(
+            old_password,
+            new_password,
+            config,
+            &[],
+            DEFAULT_MAX_DECOMPRESSED_LEN,
+            &KdfLimits::default(),
+        )
+    }
+
+    /// Changes the password of data bound to `aad`, decompressing it up to `max_len` bytes and
+    /// deriving the old key within `limits`
+    pub(crate) fn rekey_aad(
+        &self,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+        aad: &[u8],
+        max_len: usize,
+        limits: &KdfLimits,
+    ) -> Result<Self, RekeyError> {
+        let plaintext = DerivedKey::unlock_with_limits(self, old_password, limits)
+            .and_then(|key| key.open(self, aad, max_len))
+            .map_err(RekeyError::Decrypt)?;
+
+        DerivedKey::new(new_password, config)
+            .and_then(|key| key.reseal(&plaintext, self, aad))
+            .map_err(RekeyError::Encrypt)
+    }
+
+    /// Changes the password of every item with the default `Config`, collecting the failures
+    /// instead of stopping at the first one.
+    ///
+    /// The kdf runs once for the new password, the rekeyed items share one fresh salt and get
+    /// their own nonces. Items sharing an old salt also only run the kdf once. See
+    /// [`Encryptable::rekey_all_with_aad`](crate::Encryptable::rekey_all_with_aad) for items with
+    /// associated data
+    pub fn rekey_all<'a>(
+        items: impl IntoIterator<Item = &'a Self>,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<RekeyReport<T>, EncryptError>
+    where
+        T: 'a,
+    {
+        Self::rekey_all_with(items, old_password, new_password, &Config::default())
+    }
+
+    /// Changes the password of every item with `config`, see [`Encrypted::rekey_all`]
+    pub fn rekey_all_with<'a>(
+        items: impl IntoIterator<Item = &'a Self>,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+    ) -> Result<RekeyReport<T>, EncryptError>
+    where
+        T: 'a,
+    {
+        Self::rekey_all_aad(
+            items.into_iter().map(|item| (item, &[][..])),
+            old_password,
+            new_password,
+            config,
+            DEFAULT_MAX_DECOMPRESSED_LEN,
+            KdfLimits::default(),
+        )
+    }
+
+    /// Changes the password of every item, each bound to its own associated data, decompressing
+    /// them up to `max_len` bytes and deriving the old keys within `limits`
+    pub(crate) fn rekey_all_aad<'a>(
+        items: impl IntoIterator<Item = (&'a Self, &'a [u8])>,
+        old_password: &str,
+        new_password: &str,
+        config: &Config,
+        max_len: usize,
+        limits: KdfLimits,
+    ) -> Result<RekeyReport<T>, EncryptError>
+    where
+        T: 'a,
+    {
+        let new_key = DerivedKey::new(new_password, config)?;
+        let mut old_keys = KeyCache::with_limits(old_password, REKEY_CACHE_SIZE, limits);
+        let mut report = RekeyReport::default();
+
+        for (i, (item, aad)) in items.into_iter().enumerate() {
+            let rekeyed = old_keys
+                .key(item)
+                .and_then(|key| key.open(item, aad, max_len))
+                .map_err(RekeyError::Decrypt)
+                .and_then(|plaintext| {
+                    new_key
+                        .reseal(&plaintext, item, aad)
+                        .map_err(RekeyError::Encrypt)
+                });
+
+            match rekeyed {
+                Ok(encrypted) => report.rekeyed.push((i, encrypted)),
+                Err(e) => report.failed.push((i, e)),
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BytesEncrypter, Config, DecryptError, EncryptError, Encryptable, Encrypted, KdfLimits,
+        KdfParams, Padding, RekeyError, SecretBytes,
+    };
+
+    struct CostlyEncrypter;
+
+    impl Encryptable<SecretBytes> for CostlyEncrypter {
+        fn to_plaintext(data: &SecretBytes) -> Result<SecretBytes, EncryptError> {
+            Ok(data.clone())
+        }
+
+        fn from_plaintext(plaintext: SecretBytes) -> Result<SecretBytes, DecryptError> {
+            Ok(plaintext)
+        }
+
+        fn kdf_limits() -> KdfLimits {
+            KdfLimits::new().max_passes(11)
+        }
+    }
+
+    #[test]
+    fn rekey() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "old", &config).unwrap();
+
+        let rekeyed = encrypted
+            .rekey_with(
+                "old",
+                "new",
+                &config.clone().kdf(KdfParams::scrypt(4, 8, 1)),
+            )
+            .unwrap();
+        assert_ne!(rekeyed.salt, encrypted.salt);
+        assert_eq!(rekeyed.created_at(), encrypted.created_at());
+        assert_eq!(&*BytesEncrypter::decrypt(&rekeyed, "new").unwrap(), b"test");
+        assert!(BytesEncrypter::decrypt(&rekeyed, "old").is_err());
+
+        assert!(matches!(
+            encrypted.rekey_with("wrong", "new", &config),
+            Err(RekeyError::Decrypt(DecryptError::WrongPassword))
+        ));
+    }
+
+    #[test]
+    fn rekey_all() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let items = [
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"a"), "old", &config).unwrap(),
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"b"), "other", &config).unwrap(),
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"c"), "old", &config).unwrap(),
+        ];
+
+        let report = Encrypted::rekey_all_with(&items, "old", "new", &config).unwrap();
+        assert!(!report.is_ok());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, 1);
+
+        let rekeyed: Vec<_> = report
+            .rekeyed
+            .iter()
+            .map(|(i, encrypted)| (*i, BytesEncrypter::decrypt(encrypted, "new").unwrap()))
+            .collect();
+        assert_eq!(rekeyed.len(), 2);
+        assert_eq!((rekeyed[0].0, &*rekeyed[0].1), (0, &b"a"[..]));
+        assert_eq!((rekeyed[1].0, &*rekeyed[1].1), (2, &b"c"[..]));
+    }
+
+    #[test]
+    fn rekey_with_aad() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let data = SecretBytes::from(b"test");
+        let encrypted = BytesEncrypter::encrypt_with_aad(&data, "old", &config, b"row 1").unwrap();
+
+        assert!(matches!(
+            encrypted.rekey_with("old", "new", &config),
+            Err(RekeyError::Decrypt(DecryptError::Tampered))
+        ));
+
+        let rekeyed =
+            BytesEncrypter::rekey_with_aad(&encrypted, "old", "new", &config, b"row 1").unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_aad(&rekeyed, "new", b"row 1").unwrap(),
+            b"test"
+        );
+        assert!(BytesEncrypter::decrypt_with_aad(&rekeyed, "new", b"row 2").is_err());
+
+        let other = BytesEncrypter::encrypt_with_aad(&data, "old", &config, b"row 2").unwrap();
+        let report = BytesEncrypter::rekey_all_with_aad(
+            [(&encrypted, &b"row 1"[..]), (&other, &b"row 2"[..])],
+            "old",
+            "new",
+            &config,
+        )
+        .unwrap();
+        assert!(report.is_ok());
+        assert_eq!(
+            &*BytesEncrypter::decrypt_with_aad(&report.rekeyed[1].1, "new", b"row 2").unwrap(),
+            b"test"
+        );
+    }
+    #[test]
+    fn rekey_kdf_limits() {
+        // More passes than the default limits allow
+        let config = Config::new().kdf(KdfParams::argon2id(64, 11, 1));
+        let encrypted =
+            CostlyEncrypter::encrypt_with(&SecretBytes::from(b"test"), "old", &config).unwrap();
+
+        assert!(matches!(
+            encrypted.rekey_with("old", "new", &config),
+            Err(RekeyError::Decrypt(DecryptError::Malformed(_)))
+        ));
+        let report = Encrypted::rekey_all_with([&encrypted], "old", "new", &config).unwrap();
+        assert!(matches!(
+            report.failed[..],
+            [(0, RekeyError::Decrypt(DecryptError::Malformed(_)))]
+        ));
+
+        let rekeyed =
+            CostlyEncrypter::rekey_with_aad(&encrypted, "old", "new", &config, &[]).unwrap();
+        assert_eq!(
+            &*CostlyEncrypter::decrypt(&rekeyed, "new").unwrap(),
+            b"test"
+        );
+        let report =
+            CostlyEncrypter::rekey_all_with_aad([(&encrypted, &[][..])], "old", "new", &config)
+                .unwrap();
+        assert!(report.is_ok());
+    }
+    #[test]
+    fn rekey_keeps_padding_and_compression() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let padded = config.clone().padding(Padding::Block(64));
+        let encrypted =
+            BytesEncrypter::encrypt_with(&SecretBytes::from(b"test"), "old", &padded).unwrap();
+
+        let rekeyed = encrypted.rekey_with("old", "new", &config).unwrap();
+        assert_eq!(rekeyed.padding, Some(Padding::Block(64)));
+        assert_eq!(rekeyed.data.len(), encrypted.data.len());
+        assert_eq!(&*BytesEncrypter::decrypt(&rekeyed, "new").unwrap(), b"test");
+
+        let rekeyed = encrypted
+            .rekey_with("old", "new", &config.clone().padding(Padding::PowerOfTwo))
+            .unwrap();
+        assert_eq!(rekeyed.padding, Some(Padding::PowerOfTwo));
+
+        #[cfg(feature = "deflate")]
+        {
+            let compressed = config.clone().compression(crate::Compression::Deflate);
+            let data = SecretBytes::from(vec![0; 1024]);
+            let encrypted = BytesEncrypter::encrypt_with(&data, "old", &compressed).unwrap();
+
+            let rekeyed = encrypted.rekey_with("old", "new", &config).unwrap();
+            assert_eq!(rekeyed.compression, Some(crate::Compression::Deflate));
+            assert_eq!(BytesEncrypter::decrypt(&rekeyed, "new").unwrap(), data);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::{fmt, ops::Deref};
+
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+
+/// Bytes that are wiped from memory when dropped, used for plaintext going in and out of the
+/// ciphers. `Debug` does not print the contents and equality is checked in constant time.
+#[derive(Clone, Default)]
+pub struct SecretBytes(Zeroizing<Vec<u8>>);
+
+impl SecretBytes {
+    /// Wraps `bytes`, taking ownership so no copy is left behind
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Vec<u8>> for SecretBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::new(bytes)
+    }
+}
+
+impl From<&[u8]> for SecretBytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self::new(bytes.to_vec())
+    }
+}
+
+impl<const N: usize> From<&[u8; N]> for SecretBytes {
+    fn from(bytes: &[u8; N]) -> Self {
+        Self::new(bytes.to_vec())
+    }
+}
+
+impl From<&str> for SecretBytes {
+    fn from(s: &str) -> Self {
+        Self::new(s.as_bytes().to_vec())
+    }
+}
+
+impl PartialEq for SecretBytes {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ct_eq(&other.0).into()
+    }
+}
+
+impl Eq for SecretBytes {}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SecretBytes;
+
+    #[test]
+    fn redacted() {
+        let secret = SecretBytes::from("hunter2");
+
+        assert_eq!(format!("{secret:?}"), "SecretBytes([REDACTED; 7])");
+        assert_eq!(&*secret, b"hunter2");
+        assert_eq!(secret, SecretBytes::from(b"hunter2"));
+        assert_ne!(secret, SecretBytes::from(b"hunter3"));
+    }
+}
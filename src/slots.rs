@@ -0,0 +1,293 @@
+#[cfg(feature = "bincode")]
+use bincode::{Decode, Encode};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    Cipher, DecryptError, EncryptError, Encrypted, KdfAlgorithm, KdfLimits, KdfParams, RekeyError,
+};
+
+/// Most key slots an `Encrypted` can hold, password and recipient slots together
+const MAX_KEY_SLOTS: usize = 32;
+
+/// Fails if an `Encrypted` can not hold `count` key slots
+pub(crate) fn check_key_slot_count(count: usize) -> Result<(), EncryptError> {
+    match count <= MAX_KEY_SLOTS {
+        true => Ok(()),
+        false => Err(EncryptError::Kdf(
+            format!("an Encrypted holds at most {MAX_KEY_SLOTS} key slots").into(),
+        )),
+    }
+}
+
+/// The random key of an `Encrypted`, wrapped with a key derived from one password. Any slot can
+/// unlock the data, and slots can be added or removed without re-encrypting it.
+///
+/// Create data with key slots with
+/// [`Encryptable::encrypt_with_key_slots`](crate::Encryptable::encrypt_with_key_slots) or
+/// [`DerivedKey::with_key_slots`](crate::DerivedKey::with_key_slots).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "bincode", derive(Encode, Decode))]
+pub struct KeySlot {
+    pub(crate) kdf: KdfParams,
+    #[cfg_attr(feature = "serde", serde(with = "crate::bytes"))]
+    pub(crate) salt: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::bytes"))]
+    pub(crate) nonce: Vec<u8>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::bytes"))]
+    pub(crate) key: Vec<u8>,
+}
+
+impl KeySlot {
+    /// Wraps `key` with a key derived from `password`, using a fresh salt and nonce
+    pub(crate) fn new(
+        cipher: Cipher,
+        key_id: &[u8],
+        key: &[u8],
+        password: &str,
+        kdf: KdfParams,
+    ) -> Result<Self, EncryptError> {
+        if kdf.output_len != cipher.key_len() {
+            return Err(EncryptError::Kdf(
+                format!("{cipher:?} needs a {} byte key", cipher.key_len()).into(),
+            ));
+        }
+
+        let salt = kdf.generate_salt();
+        let wrapping_key = kdf
+            .derive(password.as_bytes(), &salt)
+            .map_err(EncryptError::Kdf)?;
+        let nonce = cipher.generate_nonce();
+        let key = cipher
+            .encrypt(&wrapping_key, &nonce, key_id, key)
+            .map_err(EncryptError::Cipher)?;
+
+        Ok(Self {
+            kdf,
+            salt,
+            nonce,
+            key,
+        })
+    }
+
+    /// Parameters used to derive the key of this slot from its password
+    pub fn kdf(&self) -> &KdfParams {
+        &self.kdf
+    }
+
+    /// Unwraps the key with `password`
+    fn open(
+        &self,
+        cipher: Cipher,
+        key_id: &[u8],
+        password: &str,
+        limits: &KdfLimits,
+    ) -> Result<Zeroizing<Vec<u8>>, DecryptError> {
+        self.kdf.check_limits(limits)?;
+        let wrapping_key = self
+            .kdf
+            .derive(password.as_bytes(), &self.salt)
+            .map_err(DecryptError::Kdf)?;
+        let key = cipher.decrypt(&wrapping_key, &self.nonce, key_id, &self.key)?;
+        Ok(Zeroizing::new(key.to_vec()))
+    }
+}
+
+/// Finds the first password slot `password` unlocks, returning its index and the unwrapped key
+pub(crate) fn open_key_slots(
+    cipher: Cipher,
+    key_id: &[u8],
+    slots: &[KeySlot],
+    password: &str,
+    limits: &KdfLimits,
+) -> Result<(usize, Zeroizing<Vec<u8>>), DecryptError> {
+    let password_slots = slots
+        .iter()
+        .enumerate()
+        .filter(|(_, slot)| slot.kdf.algorithm != KdfAlgorithm::X25519);
+    for (i, slot) in password_slots {
+        match slot.open(cipher, key_id, password, limits) {
+            Ok(key) => return Ok((i, key)),
+            Err(DecryptError::Authentication) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(DecryptError::WrongPassword)
+}
+
+impl<T> Encrypted<T> {
+    /// Key slots of the data, for passwords and recipients. Empty if it is encrypted with a single
+    /// password
+    pub fn key_slots(&self) -> &[KeySlot] {
+        &self.slots
+    }
+
+    /// Adds a slot for `new_password`, using the default kdf. `password` has to unlock one of the
+    /// existing slots. Up to 32 slots can be added
+    pub fn add_key_slot(&mut self, password: &str, new_password: &str) -> Result<(), RekeyError> {
+        self.add_key_slot_with(password, new_password, KdfParams::default())
+    }
+
+    /// Adds a slot for `new_password` using `kdf`, see [`Encrypted::add_key_slot`]
+    pub fn add_key_slot_with(
+        &mut self,
+        password: &str,
+        new_password: &str,
+        kdf: KdfParams,
+    ) -> Result<(), RekeyError> {
+        check_key_slot_count(self.slots.len() + 1).map_err(RekeyError::Encrypt)?;
+        let (_, key) = self.open_key_slots(password)?;
+        let slot = KeySlot::new(self.cipher, &self.salt, &key, new_password, kdf)
+            .map_err(RekeyError::Encrypt)?;
+        self.slots.push(slot);
+        Ok(())
+    }
+
+    /// Removes the slot at `index` and returns it. The last slot can not be removed, as the data
+    /// could never be decrypted again, so this returns `None` for it or an invalid index
+    pub fn remove_key_slot(&mut self, index: usize) -> Option<KeySlot> {
+        if self.slots.len() <= 1 || index >= self.slots.len() {
+            return None;
+        }
+        Some(self.slots.remove(index))
+    }
+
+    /// Replaces the slot unlocked by `old_password` with one for `new_password`, using the
+    /// default kdf. Only the key of the slot is re-encrypted, not the data
+    pub fn change_key_slot_password(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), RekeyError> {
+        self.change_key_slot_password_with(old_password, new_password, KdfParams::default())
+    }
+
+    /// Replaces the slot unlocked by `old_password` using `kdf`, see
+    /// [`Encrypted::change_key_slot_password`]
+    pub fn change_key_slot_password_with(
+        &mut self,
+        old_password: &str,
+        new_password: &str,
+        kdf: KdfParams,
+    ) -> Result<(), RekeyError> {
+        let (i, key) = self.open_key_slots(old_password)?;
+        self.slots[i] = KeySlot::new(self.cipher, &self.salt, &key, new_password, kdf)
+            .map_err(RekeyError::Encrypt)?;
+        Ok(())
+    }
+
+    fn open_key_slots(&self, password: &str) -> Result<(usize, Zeroizing<Vec<u8>>), RekeyError> {
+        if self.kdf.algorithm != KdfAlgorithm::KeySlots {
+            return Err(RekeyError::Decrypt(DecryptError::Malformed(
+                "data is not encrypted with key slots",
+            )));
+        }
+        open_key_slots(
+            self.cipher,
+            &self.salt,
+            &self.slots,
+            password,
+            &KdfLimits::default(),
+        )
+        .map_err(RekeyError::Decrypt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        BytesEncrypter, Config, DecryptError, DerivedKey, EncryptError, Encryptable, Encrypted,
+        KdfParams, RekeyError, SecretBytes,
+    };
+
+    #[test]
+    fn key_slots() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let mut encrypted = BytesEncrypter::encrypt_with_key_slots(
+            &SecretBytes::from(b"test"),
+            &["user", "recovery"],
+            &config,
+        )
+        .unwrap();
+        assert_eq!(encrypted.key_slots().len(), 2);
+        let data = encrypted.data.clone();
+
+        for password in ["user", "recovery"] {
+            assert_eq!(
+                &*BytesEncrypter::decrypt(&encrypted, password).unwrap(),
+                b"test"
+            );
+        }
+        assert!(matches!(
+            BytesEncrypter::decrypt(&encrypted, "admin"),
+            Err(DecryptError::WrongPassword)
+        ));
+
+        encrypted
+            .add_key_slot_with("recovery", "admin", config.kdf)
+            .unwrap();
+        encrypted
+            .change_key_slot_password_with("user", "new user", config.kdf)
+            .unwrap();
+        assert!(encrypted.remove_key_slot(1).is_some());
+        assert_eq!(encrypted.data, data);
+
+        let encrypted = Encrypted::from_bytes(&encrypted.to_bytes().unwrap()).unwrap();
+        for password in ["new user", "admin"] {
+            assert_eq!(
+                &*BytesEncrypter::decrypt(&encrypted, password).unwrap(),
+                b"test"
+            );
+        }
+        for password in ["user", "recovery"] {
+            assert!(BytesEncrypter::decrypt(&encrypted, password).is_err());
+        }
+
+        let key = DerivedKey::unlock(&encrypted, "admin").unwrap();
+        let other = BytesEncrypter::encrypt_with_key(&SecretBytes::from(b"other"), &key).unwrap();
+        assert_eq!(
+            &*BytesEncrypter::decrypt(&other, "new user").unwrap(),
+            b"other"
+        );
+    }
+
+    #[test]
+    fn key_slot_limits() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let mut encrypted =
+            BytesEncrypter::encrypt_with_key_slots(&SecretBytes::default(), &["user"], &config)
+                .unwrap();
+        assert!(encrypted.remove_key_slot(0).is_none());
+        assert!(matches!(
+            encrypted.add_key_slot_with("wrong", "admin", config.kdf),
+            Err(RekeyError::Decrypt(DecryptError::WrongPassword))
+        ));
+
+        let passwords = ["user"; 33];
+        assert!(matches!(
+            DerivedKey::with_key_slots(&passwords, &config),
+            Err(EncryptError::Kdf(_))
+        ));
+        let mut full = BytesEncrypter::encrypt_with_key_slots(
+            &SecretBytes::default(),
+            &passwords[1..],
+            &config,
+        )
+        .unwrap();
+        assert!(matches!(
+            full.add_key_slot_with("user", "admin", config.kdf),
+            Err(RekeyError::Encrypt(EncryptError::Kdf(_)))
+        ));
+        assert!(<Encrypted>::from_bytes(&full.to_bytes().unwrap()).is_ok());
+
+        let mut single =
+            BytesEncrypter::encrypt_with(&SecretBytes::default(), "user", &config).unwrap();
+        assert!(single.key_slots().is_empty());
+        assert!(matches!(
+            single.add_key_slot_with("user", "admin", config.kdf),
+            Err(RekeyError::Decrypt(DecryptError::Malformed(_)))
+        ));
+    }
+}
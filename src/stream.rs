@@ -0,0 +1,605 @@
+use std::{
+    io::{self, Read, Write},
+    time::SystemTime,
+};
+
+use rand::{thread_rng, RngCore};
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::{
+    Cipher, Config, DecryptError, DerivedKey, EncryptError, Encrypted, SecretBytes, SecretKey,
+};
+
+/// Plaintext bytes in each chunk unless changed with [`EncryptWriter::chunk_size`]
+const DEFAULT_CHUNK_SIZE: u32 = 64 * 1024;
+/// Largest chunk size a stream can use, so a header can not make the reader allocate too much
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+/// Largest header a reader reads before giving up
+const MAX_HEADER_LEN: usize = 64 * 1024;
+/// Bytes of the nonce taken by the chunk counter and the last chunk flag
+const NONCE_SUFFIX_LEN: usize = 5;
+/// Length of the random salt the key of a stream is derived with, stored as its nonce
+const STREAM_SALT_LEN: usize = 32;
+/// Length of the authentication tag every chunk gets
+const TAG_LEN: usize = 16;
+/// Size of the buffer used to read from the inner reader
+pub(crate) const READ_BUFFER_LEN: usize = 8 * 1024;
+
+/// Nonce of chunk `counter`: zeros, the counter and whether this is the last chunk. Every stream
+/// has its own key, so the nonces only need to be unique within the stream
+fn chunk_nonce(cipher: Cipher, counter: u32, last: bool) -> Vec<u8> {
+    let mut nonce = vec![0u8; cipher.nonce_len() - NONCE_SUFFIX_LEN];
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(last as u8);
+    nonce
+}
+
+/// Splits plaintext into chunks and encrypts them, without doing any I/O. Shared by the writers
+pub(crate) struct StreamSealer {
+    key: DerivedKey,
+    header: Encrypted,
+    chunk_size: u32,
+    aad: Option<Vec<u8>>,
+    buffer: Zeroizing<Vec<u8>>,
+    counter: u32,
+}
+
+impl StreamSealer {
+    pub(crate) fn new(key: &DerivedKey) -> Result<Self, EncryptError> {
+        let cipher = key.cipher();
+        if cipher == Cipher::Fernet {
+            return Err(EncryptError::Cipher(
+                "fernet does not support streaming".into(),
+            ));
+        }
+        if cipher == Cipher::Age {
+            return Err(EncryptError::Cipher(
+                "age does not support streaming".into(),
+            ));
+        }
+
+        let mut stream_salt = vec![0u8; STREAM_SALT_LEN];
+        thread_rng().fill_bytes(&mut stream_salt);
+
+        Ok(Self {
+            key: key.for_stream(&stream_salt),
+            header: key.template(stream_salt)?,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            aad: None,
+            buffer: Zeroizing::new(Vec::new()),
+            counter: 0,
+        })
+    }
+
+    /// Sets the chunk size, unless the header was already sealed
+    pub(crate) fn set_chunk_size(&mut self, chunk_size: u32) {
+        if self.aad.is_none() {
+            self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        }
+    }
+
+    /// Whether the buffer holds a full chunk, which has to be sealed before more data is buffered
+    pub(crate) fn is_full(&self) -> bool {
+        self.buffer.len() == self.chunk_size as usize
+    }
+
+    /// Buffers as much of `buf` as fits in the current chunk, returning how much that was
+    pub(crate) fn buffer(&mut self, buf: &[u8]) -> usize {
+        let n = (self.chunk_size as usize - self.buffer.len()).min(buf.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        n
+    }
+
+    /// Encrypts the buffered plaintext as the next chunk. The header is returned in front of the
+    /// first chunk
+    pub(crate) fn seal(&mut self, last: bool) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let aad = match &self.aad {
+            Some(aad) => aad,
+            None => {
+                out = self
+                    .header
+                    .stream_header(self.chunk_size)
+                    .map_err(io::Error::other)?;
+                let aad = self
+                    .header
+                    .stream_aad(self.chunk_size)
+                    .map_err(io::Error::other)?;
+                self.aad.insert(aad)
+            }
+        };
+
+        let nonce = chunk_nonce(self.key.cipher(), self.counter, last);
+        let chunk = self
+            .key
+            .cipher()
+            .encrypt(self.key.bytes(), &nonce, aad, &self.buffer)
+            .map_err(|e| io::Error::other(EncryptError::Cipher(e)))?;
+        out.extend_from_slice(&chunk);
+        self.buffer.zeroize();
+
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("stream has too many chunks"))?;
+        Ok(out)
+    }
+}
+
+/// Progress of a `StreamOpener`
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Reading,
+    Done,
+    Failed,
+}
+
+/// Decrypts the chunks of a stream fed to it, without doing any I/O. Shared by the readers
+pub(crate) struct StreamOpener {
+    key: DerivedKey,
+    aad: Vec<u8>,
+    /// Whether the header has a key check, so chunks that fail to decrypt were tampered with
+    key_checked: bool,
+    chunk_size: usize,
+    pending: Vec<u8>,
+    eof: bool,
+    plaintext: SecretBytes,
+    pos: usize,
+    counter: u32,
+    state: State,
+}
+
+impl StreamOpener {
+    /// Checks the header of a stream read by [`parse_header`]
+    pub(crate) fn new(
+        key: DerivedKey,
+        mut header: Encrypted,
+        chunk_size: u32,
+    ) -> Result<Self, DecryptError> {
+        if header.cipher == Cipher::Fernet {
+            return Err(DecryptError::Malformed("fernet does not support streaming"));
+        }
+        if header.nonce.len() != STREAM_SALT_LEN {
+            return Err(DecryptError::Malformed("stream salt has the wrong length"));
+        }
+        if header.compression.is_some() {
+            return Err(DecryptError::Malformed("streams can not be compressed"));
+        }
+        if header.padding.is_some() {
+            return Err(DecryptError::Malformed("streams can not be padded"));
+        }
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(DecryptError::Malformed("chunk size is out of range"));
+        }
+        if !key.checks(&header) {
+            return Err(DecryptError::WrongPassword);
+        }
+        header.check_expiry(SystemTime::now())?;
+
+        Ok(Self {
+            key: key.for_stream(&header.nonce),
+            aad: header
+                .stream_aad(chunk_size)
+                .map_err(|_| DecryptError::Malformed("header field is too long"))?,
+            key_checked: !header.key_check.is_empty(),
+            pending: std::mem::take(&mut header.data),
+            chunk_size: chunk_size as usize,
+            eof: false,
+            plaintext: SecretBytes::default(),
+            pos: 0,
+            counter: 0,
+            state: State::Reading,
+        })
+    }
+
+    /// Number of bytes to read before the next chunk can be opened. A chunk is the last one when
+    /// the stream ends before a byte past it, so one byte more than a chunk is read ahead
+    pub(crate) fn wanted(&self) -> usize {
+        if self.eof {
+            return 0;
+        }
+        (self.chunk_size + TAG_LEN + 1).saturating_sub(self.pending.len())
+    }
+
+    /// Adds bytes read from the stream, an empty slice marks its end
+    pub(crate) fn fill(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            self.eof = true;
+        }
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Copies plaintext into `buf`, opening the next chunk when needed. Returns `None` if more
+    /// bytes have to be read first, see [`StreamOpener::wanted`]
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> Option<io::Result<usize>> {
+        while self.pos == self.plaintext.len() {
+            match self.state {
+                State::Done => return Some(Ok(0)),
+                State::Failed => {
+                    return Some(Err(invalid_data(DecryptError::Malformed(
+                        "stream failed to decrypt",
+                    ))))
+                }
+                State::Reading if self.wanted() > 0 => return None,
+                State::Reading => {}
+            }
+            if let Err(e) = self.open_chunk() {
+                self.state = State::Failed;
+                return Some(Err(invalid_data(e)));
+            }
+        }
+
+        let n = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..n].copy_from_slice(&self.plaintext[self.pos..self.pos + n]);
+        self.pos += n;
+        Some(Ok(n))
+    }
+
+    fn open_chunk(&mut self) -> Result<(), DecryptError> {
+        let encrypted_len = self.chunk_size + TAG_LEN;
+        let last = self.pending.len() <= encrypted_len;
+        let chunk: Vec<u8> = self
+            .pending
+            .drain(..self.pending.len().min(encrypted_len))
+            .collect();
+        let cipher = self.key.cipher();
+        let nonce = chunk_nonce(cipher, self.counter, last);
+        self.plaintext = match cipher.decrypt(self.key.bytes(), &nonce, &self.aad, &chunk) {
+            Ok(plaintext) => plaintext,
+            Err(DecryptError::Authentication) => {
+                // A chunk without room for a tag, or a full chunk that opens as a middle chunk,
+                // means the stream was cut off
+                let nonce = chunk_nonce(cipher, self.counter, false);
+                let cut_off = chunk.len() < TAG_LEN
+                    || chunk.len() == encrypted_len
+                        && cipher
+                            .decrypt(self.key.bytes(), &nonce, &self.aad, &chunk)
+                            .is_ok();
+                return Err(match (cut_off, self.key_checked) {
+                    (true, _) => DecryptError::Truncated,
+                    (false, true) => DecryptError::Tampered,
+                    (false, false) => DecryptError::Authentication,
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        self.pos = 0;
+
+        if last {
+            self.state = State::Done;
+        } else {
+            self.counter = self
+                .counter
+                .checked_add(1)
+                .ok_or(DecryptError::Malformed("stream has too many chunks"))?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid_data(e: DecryptError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Parses the header of a stream from the bytes read so far. Returns `None` if more bytes are
+/// needed, otherwise the header, holding the bytes after it as data, and the chunk size
+pub(crate) fn parse_header(bytes: &[u8]) -> Result<Option<(Encrypted, u32)>, DecryptError> {
+    match Encrypted::parse(bytes) {
+        Ok((header, Some(chunk_size))) => Ok(Some((header, chunk_size))),
+        Ok((_, None)) => Err(DecryptError::Malformed("data is not a stream")),
+        Err(DecryptError::Truncated) if bytes.len() < MAX_HEADER_LEN => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Encrypts everything written to it into `W`, so data larger than memory can be encrypted. Read
+/// it back with a [`DecryptReader`].
+///
+/// The output starts with the same header as [`Encrypted::to_bytes`], so the key is derived once
+/// from the password and salt. Each stream is encrypted with its own key, derived from that key
+/// and a random 32 byte salt stored in place of the nonce, so one `DerivedKey` can encrypt any
+/// number of streams with every cipher. The plaintext is split into chunks of 64 KiB, each
+/// encrypted on its own with a nonce made from the chunk's index and a flag set on the last
+/// chunk, like the STREAM construction. Chunks that are removed, reordered or cut off at the end
+/// fail to decrypt.
+///
+/// [`EncryptWriter::finish`] must be called to write the last chunk, dropping the writer without
+/// it leaves a stream that fails to decrypt. Fernet and age are not supported.
+///
+/// ```
+/// use std::io::{Read, Write};
+///
+/// use encryptable::{Config, DecryptReader, EncryptWriter, KdfParams};
+///
+/// let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+/// let mut writer = EncryptWriter::new(Vec::new(), "password", &config).unwrap();
+/// writer.write_all(b"test").unwrap();
+/// let encrypted = writer.finish().unwrap();
+///
+/// let mut reader = DecryptReader::new(&encrypted[..], "password").unwrap();
+/// let mut decrypted = Vec::new();
+/// reader.read_to_end(&mut decrypted).unwrap();
+/// assert_eq!(decrypted, b"test");
+/// ```
+pub struct EncryptWriter<W: Write> {
+    inner: W,
+    sealer: StreamSealer,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    /// Derives a key from `password` using the cipher and kdf of `config`, and encrypts into
+    /// `inner` with it. Nothing is written until the first write
+    pub fn new(inner: W, password: &str, config: &Config) -> Result<Self, EncryptError> {
+        Self::with_key(inner, &DerivedKey::new(password, config)?)
+    }
+
+    /// Encrypts into `inner` with an already derived `key`, which can also hold key slots or
+    /// recipients
+    pub fn with_key(inner: W, key: &DerivedKey) -> Result<Self, EncryptError> {
+        Ok(Self {
+            inner,
+            sealer: StreamSealer::new(key)?,
+        })
+    }
+
+    /// Sets the number of plaintext bytes in each chunk, between 1 byte and 16 MiB. Has no effect
+    /// once data was written
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.sealer.set_chunk_size(chunk_size);
+        self
+    }
+
+    /// Writes the last chunk and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let chunk = self.sealer.seal(true)?;
+        self.inner.write_all(&chunk)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            // A full chunk is only written once more data follows, so the last chunk is never
+            // empty unless the whole stream is
+            if self.sealer.is_full() {
+                let chunk = self.sealer.seal(false)?;
+                self.inner.write_all(&chunk)?;
+            }
+            written += self.sealer.buffer(&buf[written..]);
+        }
+        Ok(written)
+    }
+
+    /// Flushes the inner writer. Buffered plaintext is only written once a chunk is full or the
+    /// stream is finished
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts a stream written by an [`EncryptWriter`] while reading it from `R`.
+///
+/// The header is read and the key derived when the reader is made. Every chunk is authenticated
+/// before any of its plaintext is returned, errors are returned as [`io::ErrorKind::InvalidData`]
+/// holding a [`DecryptError`]. A stream that was cut off fails on its last chunk, so all data
+/// must be read before trusting any of it.
+pub struct DecryptReader<R: Read> {
+    inner: R,
+    opener: StreamOpener,
+}
+
+impl<R: Read> DecryptReader<R> {
+    /// Reads the header from `inner` and derives the key from `password`, or unwraps it from the
+    /// first key slot `password` unlocks
+    pub fn new(mut inner: R, password: &str) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner)?;
+        let key = DerivedKey::unlock(&header, password)?;
+        Self::from_parts(inner, key, header, chunk_size)
+    }
+
+    /// Reads the header from `inner` and unwraps the key from the recipient key slot of
+    /// `secret_key`
+    pub fn with_secret_key(mut inner: R, secret_key: &SecretKey) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner)?;
+        let key = DerivedKey::unlock_with_secret_key(&header, secret_key)?;
+        Self::from_parts(inner, key, header, chunk_size)
+    }
+
+    /// Reads the header from `inner` and decrypts with an already derived `key`
+    pub fn with_key(mut inner: R, key: &DerivedKey) -> Result<Self, DecryptError> {
+        let (header, chunk_size) = read_header(&mut inner)?;
+        if !key.matches(&header) {
+            return Err(DecryptError::KeyMismatch);
+        }
+        Self::from_parts(inner, key.clone(), header, chunk_size)
+    }
+
+    /// Returns the inner reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn from_parts(
+        inner: R,
+        key: DerivedKey,
+        header: Encrypted,
+        chunk_size: u32,
+    ) -> Result<Self, DecryptError> {
+        Ok(Self {
+            inner,
+            opener: StreamOpener::new(key, header, chunk_size)?,
+        })
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read_buf = [0u8; READ_BUFFER_LEN];
+        loop {
+            if let Some(result) = self.opener.read(buf) {
+                return result;
+            }
+            let want = self.opener.wanted().min(read_buf.len());
+            match self.inner.read(&mut read_buf[..want]) {
+                Ok(n) => self.opener.fill(&read_buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Reads the header of a stream from `inner`
+fn read_header<R: Read>(inner: &mut R) -> Result<(Encrypted, u32), DecryptError> {
+    let mut bytes = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        if let Some(header) = parse_header(&bytes)? {
+            return Ok(header);
+        }
+        match inner.read(&mut buf) {
+            Ok(0) => return Err(DecryptError::Truncated),
+            Ok(n) => bytes.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(DecryptError::Io(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+
+    use crate::{
+        Cipher, Config, DecryptError, DecryptReader, DerivedKey, EncryptWriter, Encrypted,
+        KdfParams, SecretKey,
+    };
+
+    fn encrypt(data: &[u8], key: &DerivedKey, chunk_size: u32) -> Vec<u8> {
+        let mut writer = EncryptWriter::with_key(Vec::new(), key)
+            .unwrap()
+            .chunk_size(chunk_size);
+        // Uneven writes so chunks do not line up with them
+        for part in data.chunks(7) {
+            writer.write_all(part).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(stream: &[u8], key: &DerivedKey) -> io::Result<Vec<u8>> {
+        let mut reader = DecryptReader::with_key(stream, key).map_err(io::Error::other)?;
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn decrypt_error(stream: &[u8], key: &DerivedKey) -> DecryptError {
+        let e = decrypt(stream, key).unwrap_err();
+        *e.into_inner().unwrap().downcast::<DecryptError>().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let key = DerivedKey::new("password", &config).unwrap();
+        let data: Vec<u8> = (0..1_000).map(|i| i as u8).collect();
+
+        for len in [0, 1, 99, 100, 101, 1_000] {
+            let stream = encrypt(&data[..len], &key, 100);
+            assert_eq!(decrypt(&stream, &key).unwrap(), &data[..len]);
+        }
+
+        let stream = encrypt(&data, &key, 100);
+        let mut reader = DecryptReader::new(&stream[..], "password").unwrap();
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, data);
+
+        assert!(matches!(
+            DecryptReader::new(&stream[..], "wrong"),
+            Err(DecryptError::WrongPassword)
+        ));
+        assert!(matches!(
+            <Encrypted>::from_bytes(&stream),
+            Err(DecryptError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn recipients() {
+        let secret_key = SecretKey::generate();
+        let key = DerivedKey::with_recipients(&[secret_key.public_key()], &Config::new()).unwrap();
+        let stream = encrypt(b"test", &key, 100);
+
+        let mut reader = DecryptReader::with_secret_key(&stream[..], &secret_key).unwrap();
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, b"test");
+    }
+
+    #[test]
+    fn tampering() {
+        let config = Config::new().kdf(KdfParams::pbkdf2(1_000));
+        let key = DerivedKey::new("password", &config).unwrap();
+        let data = vec![1u8; 1_000];
+        let stream = encrypt(&data, &key, 100);
+        let chunk = 100 + 16;
+        let header = stream.len() - 10 * chunk;
+
+        // Cut off at a chunk boundary
+        for len in [header, header + chunk, stream.len() - chunk] {
+            assert!(matches!(
+                decrypt_error(&stream[..len], &key),
+                DecryptError::Truncated
+            ));
+        }
+        // In the middle of a chunk it can not be told apart from a changed last chunk
+        assert!(matches!(
+            decrypt_error(&stream[..stream.len() - 1], &key),
+            DecryptError::Tampered
+        ));
+
+        let mut swapped = stream[..header].to_vec();
+        swapped.extend_from_slice(&stream[header + chunk..header + 2 * chunk]);
+        swapped.extend_from_slice(&stream[header..header + chunk]);
+        swapped.extend_from_slice(&stream[header + 2 * chunk..]);
+        assert!(matches!(
+            decrypt_error(&swapped, &key),
+            DecryptError::Tampered
+        ));
+
+        let mut extended = stream.clone();
+        extended.extend_from_slice(&stream[header..header + chunk]);
+        assert!(decrypt(&extended, &key).is_err());
+
+        assert!(matches!(
+            DecryptReader::with_key(&stream[..header - 1], &key),
+            Err(DecryptError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn stream_keys() {
+        let config = Config::new()
+            .kdf(KdfParams::pbkdf2(1_000))
+            .cipher(Cipher::Aes256Gcm);
+        let key = DerivedKey::new("password", &config).unwrap();
+        let first = encrypt(b"test", &key, 100);
+        let second = encrypt(b"test", &key, 100);
+        let header = first.len() - (4 + 16);
+
+        // Same key and plaintext, but each stream has its own salt and key
+        assert_ne!(first[..header], second[..header]);
+        assert_ne!(first[header..], second[header..]);
+
+        let mut spliced = first[..header].to_vec();
+        spliced.extend_from_slice(&second[header..]);
+        assert!(matches!(
+            decrypt_error(&spliced, &key),
+            DecryptError::Tampered
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Runs the age test vectors from https://github.com/C2SP/CCTV/tree/main/age
+
+use std::{fs, path::Path};
+
+use encryptable::{
+    BytesEncrypter, Cipher, Config, DecryptError, Encryptable, Encrypted, KdfParams, SecretKey,
+};
+use sha2::{Digest, Sha256};
+
+/// One test vector, metadata lines followed by an empty line and the age file
+struct TestFile {
+    expect: String,
+    payload_sha256: Option<String>,
+    identity: Option<SecretKey>,
+    passphrase: Option<String>,
+    file: Vec<u8>,
+}
+
+impl TestFile {
+    fn parse(contents: &[u8]) -> Self {
+        let mut test = Self {
+            expect: String::new(),
+            payload_sha256: None,
+            identity: None,
+            passphrase: None,
+            file: Vec::new(),
+        };
+
+        let mut rest = contents;
+        loop {
+            let end = rest.iter().position(|&b| b == b'\n').unwrap();
+            let line = std::str::from_utf8(&rest[..end]).unwrap();
+            rest = &rest[end + 1..];
+            if line.is_empty() {
+                break;
+            }
+
+            let (key, value) = line.split_once(": ").unwrap();
+            match key {
+                "expect" => test.expect = value.to_string(),
+                "payload" => test.payload_sha256 = Some(value.to_string()),
+                "identity" => test.identity = Some(value.parse().unwrap()),
+                "passphrase" => test.passphrase = Some(value.to_string()),
+                "file key" | "armored" | "comment" => {}
+                _ => panic!("unknown test vector metadata {key}"),
+            }
+        }
+        test.file = rest.to_vec();
+        test
+    }
+}
+
+#[test]
+fn test_vectors() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/testdata/age");
+    let mut count = 0;
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        let test = TestFile::parse(&fs::read(&path).unwrap());
+
+        // Armored files are read as text, like an armored `Encrypted`
+        let encrypted: Result<Encrypted, _> = match test.file.starts_with(b"age-encryption.org/") {
+            true => Encrypted::from_bytes(&test.file),
+            false => std::str::from_utf8(&test.file)
+                .map_err(|_| DecryptError::Malformed("armor is not utf-8"))
+                .and_then(str::parse),
+        };
+        let result = encrypted.and_then(|encrypted| match (&test.identity, &test.passphrase) {
+            (Some(identity), _) => BytesEncrypter::decrypt_with_secret_key(&encrypted, identity),
+            (None, Some(passphrase)) => BytesEncrypter::decrypt(&encrypted, passphrase),
+            (None, None) => panic!("{name} has no identity or passphrase"),
+        });
+
+        match (test.expect.as_str(), result) {
+            ("success", Ok(payload)) => {
+                let sha256: String = Sha256::digest(&*payload)
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect();
+                assert_eq!(Some(sha256), test.payload_sha256, "{name}");
+            }
+            (
+                "no match",
+                Err(
+                    DecryptError::Authentication
+                    | DecryptError::KeyMismatch
+                    | DecryptError::WrongPassword,
+                ),
+            )
+            | ("HMAC failure", Err(DecryptError::Tampered))
+            | ("header failure" | "armor failure", Err(DecryptError::Malformed(_)))
+            | ("payload failure", Err(DecryptError::Tampered | DecryptError::Malformed(_))) => {}
+            (expect, result) => panic!("{name}: expected {expect}, got {result:?}"),
+        }
+        count += 1;
+    }
+    assert!(count > 100);
+}
+
+/// Files written here are read by the `age` crate, and the other way around
+#[test]
+fn interop() {
+    use std::io::{Read, Write};
+
+    use ::age::{secrecy::SecretString, Identity, Recipient};
+    use encryptable::SecretBytes;
+
+    let secret_key = SecretKey::generate();
+    let identity: ::age::x25519::Identity = secret_key.to_age_string().parse().unwrap();
+    let recipient: ::age::x25519::Recipient = secret_key.public_key().to_string().parse().unwrap();
+    let data = SecretBytes::new((0..100_000).map(|i| i as u8).collect());
+
+    let decrypt = |file: &[u8], identity: &dyn Identity| {
+        let reader = ::age::armor::ArmoredReader::new(file);
+        let mut out = Vec::new();
+        ::age::Decryptor::new(reader)
+            .unwrap()
+            .decrypt(std::iter::once(identity))
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    };
+    let encrypt = |recipient: &dyn Recipient| {
+        let mut out = Vec::new();
+        let mut writer = ::age::Encryptor::with_recipients(std::iter::once(recipient))
+            .unwrap()
+            .wrap_output(&mut out)
+            .unwrap();
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+        out
+    };
+
+    let config = Config::new()
+        .cipher(Cipher::Age)
+        .kdf(KdfParams::scrypt(4, 8, 1));
+    let encrypted =
+        BytesEncrypter::encrypt_to_recipients(&data, &[secret_key.public_key()], &config).unwrap();
+    assert_eq!(decrypt(&encrypted.to_bytes().unwrap(), &identity), &*data);
+    assert_eq!(decrypt(encrypted.to_string().as_bytes(), &identity), &*data);
+
+    let encrypted = BytesEncrypter::encrypt_with(&data, "password", &config).unwrap();
+    let passphrase = ::age::scrypt::Identity::new(SecretString::from("password".to_string()));
+    assert_eq!(decrypt(&encrypted.to_bytes().unwrap(), &passphrase), &*data);
+    assert_eq!(
+        decrypt(encrypted.to_string().as_bytes(), &passphrase),
+        &*data
+    );
+
+    let encrypted = Encrypted::from_bytes(&encrypt(&recipient)).unwrap();
+    assert_eq!(
+        BytesEncrypter::decrypt_with_secret_key(&encrypted, &secret_key).unwrap(),
+        data
+    );
+
+    let mut passphrase = ::age::scrypt::Recipient::new(SecretString::from("password".to_string()));
+    passphrase.set_work_factor(4);
+    let encrypted = Encrypted::from_bytes(&encrypt(&passphrase)).unwrap();
+    assert_eq!(
+        BytesEncrypter::decrypt(&encrypted, "password").unwrap(),
+        data
+    );
+}
+
+/// Any `Encryptable` can read and write age files, the plaintext is its `to_plaintext` bytes
+#[test]
+fn encryptable() {
+    use std::io::Read;
+
+    use encryptable::{EncryptError, SecretBytes};
+
+    struct Name;
+
+    impl Encryptable<String> for Name {
+        fn to_plaintext(data: &String) -> Result<SecretBytes, EncryptError> {
+            Ok(SecretBytes::from(data.as_str()))
+        }
+
+        fn from_plaintext(plaintext: SecretBytes) -> Result<String, DecryptError> {
+            String::from_utf8(plaintext.to_vec())
+                .map_err(|_| DecryptError::Malformed("name is not utf-8"))
+        }
+
+        fn config() -> Config {
+            Config::new()
+                .cipher(Cipher::Age)
+                .kdf(KdfParams::scrypt(4, 8, 1))
+        }
+    }
+
+    let secret_key = SecretKey::generate();
+    let identity: ::age::x25519::Identity = secret_key.to_age_string().parse().unwrap();
+    let name = "Ferris".to_string();
+
+    let encrypted = Name::encrypt(&name, "password").unwrap();
+    let armored = encrypted.to_string();
+    assert!(armored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+    assert_eq!(
+        Name::decrypt(&armored.parse::<Encrypted<String>>().unwrap(), "password").unwrap(),
+        name
+    );
+
+    let encrypted =
+        Name::encrypt_to_recipients(&name, &[secret_key.public_key()], &Name::config()).unwrap();
+    let mut out = Vec::new();
+    ::age::Decryptor::new(&encrypted.to_bytes().unwrap()[..])
+        .unwrap()
+        .decrypt(std::iter::once(&identity as &dyn ::age::Identity))
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    assert_eq!(out, name.as_bytes());
+}
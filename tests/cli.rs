@@ -0,0 +1,66 @@
+//! Runs the `encryptable` binary
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+fn run(args: &[&str], passphrase: &str, stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_encryptable"))
+        .args(args)
+        .args(["--passphrase-env", "PASSPHRASE"])
+        .env("PASSPHRASE", passphrase)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn round_trip() {
+    for args in [
+        &["encrypt"][..],
+        &["encrypt", "--armor", "--cipher", "aes256gcm"],
+    ] {
+        let encrypted = run(args, "password", b"test");
+        assert!(encrypted.status.success());
+
+        let decrypted = run(&["decrypt"], "password", &encrypted.stdout);
+        assert!(decrypted.status.success());
+        assert_eq!(decrypted.stdout, b"test");
+    }
+
+    let armored = run(&["encrypt", "--armor"], "password", b"test").stdout;
+    assert!(armored.starts_with(b"-----BEGIN ENCRYPTABLE-----\n"));
+}
+
+#[test]
+fn exit_codes() {
+    let encrypted = run(&["encrypt"], "password", b"test").stdout;
+    assert_eq!(
+        run(&["decrypt"], "wrong", &encrypted).status.code(),
+        Some(3)
+    );
+    assert_eq!(
+        run(&["decrypt"], "password", b"not encrypted")
+            .status
+            .code(),
+        Some(4)
+    );
+    assert_eq!(
+        run(&["decrypt"], "password", &encrypted[..20])
+            .status
+            .code(),
+        Some(4)
+    );
+    let mut tampered = encrypted.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(
+        run(&["decrypt"], "password", &tampered).status.code(),
+        Some(4)
+    );
+    assert_eq!(run(&["unknown"], "password", b"").status.code(), Some(2));
+}
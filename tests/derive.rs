@@ -0,0 +1,148 @@
+use encryptable::{
+    Cipher, Config, DecryptError, DerivedKey, EncryptFields, Encryptable, KdfParams,
+};
+use serde::{Deserialize, Serialize};
+
+fn fast_kdf() -> Config {
+    Config::new().kdf(KdfParams::pbkdf2(1_000))
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Encryptable)]
+#[encryptable(config = fast_kdf)]
+struct User {
+    name: String,
+    age: u8,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Encryptable)]
+#[encryptable(config = fast_kdf, tag = "admin-v1")]
+struct Admin {
+    name: String,
+    age: u8,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Encryptable)]
+#[encryptable(config = fast_kdf, cipher = Aes256Gcm)]
+enum Secret<T> {
+    Token(T),
+    Nothing,
+}
+
+#[derive(Debug, PartialEq, EncryptFields)]
+#[encryptable(config = fast_kdf, sealed_derive(Debug, Clone, Serialize, Deserialize))]
+struct Record {
+    id: u64,
+    #[encrypt]
+    ssn: String,
+    #[encrypt]
+    notes: String,
+    #[encrypt]
+    api_token: Option<Vec<u8>>,
+}
+
+#[test]
+fn derive_struct() {
+    let user = User {
+        name: "name".to_string(),
+        age: 20,
+    };
+
+    let encrypted = user.encrypt("password").unwrap();
+    assert_eq!(User::decrypt(&encrypted, "password").unwrap(), user);
+    assert!(matches!(
+        User::decrypt(&encrypted, "incorrect password"),
+        Err(DecryptError::WrongPassword)
+    ));
+}
+
+#[test]
+fn derive_type_tag() {
+    let admin = Admin {
+        name: "name".to_string(),
+        age: 20,
+    };
+
+    let encrypted = admin.encrypt("password").unwrap();
+    assert_eq!(encrypted.type_tag(), Some("admin-v1"));
+    assert_eq!(Admin::decrypt(&encrypted, "password").unwrap(), admin);
+
+    // Same fields, but the tag does not match
+    assert!(matches!(
+        User::decrypt(&encrypted.cast(), "password"),
+        Err(DecryptError::TypeMismatch)
+    ));
+}
+
+#[test]
+fn derive_generic_enum() {
+    let secret = Secret::Token(vec![1u8, 2, 3]);
+
+    let encrypted = secret.encrypt("password").unwrap();
+    let bytes = encrypted.to_bytes().unwrap();
+    assert_eq!(bytes[5], 2, "cipher id of AES-256-GCM");
+    assert_eq!(Secret::decrypt(&encrypted, "password").unwrap(), secret);
+
+    let nothing = Secret::<u8>::encrypt_with(&Secret::Nothing, "password", &fast_kdf()).unwrap();
+    assert_eq!(
+        Secret::<u8>::decrypt(&nothing, "password").unwrap(),
+        Secret::Nothing
+    );
+    assert_eq!(
+        <User as Encryptable<User>>::config().cipher(Cipher::Aes256Gcm),
+        <Secret<u8> as Encryptable<Secret<u8>>>::config()
+    );
+}
+
+#[test]
+fn derive_encrypt_fields() {
+    let record = Record {
+        id: 7,
+        ssn: "123-45-6789".to_string(),
+        notes: "notes".to_string(),
+        api_token: Some(vec![1, 2, 3]),
+    };
+
+    let sealed: RecordSealed = record.seal_with_password("password").unwrap();
+    assert_eq!(sealed.id, 7);
+    assert_eq!(sealed.ssn.to_bytes().unwrap()[..4], *b"ENCR");
+    assert_eq!(sealed.unseal_with_password("password").unwrap(), record);
+    assert!(matches!(
+        sealed.unseal_with_password("incorrect password"),
+        Err(DecryptError::WrongPassword)
+    ));
+
+    let key = DerivedKey::unlock(&sealed.ssn, "password").unwrap();
+    assert_eq!(sealed.clone().unseal(&key).unwrap(), record);
+    assert_eq!(record.seal(&key).unwrap().unseal(&key).unwrap(), record);
+}
+
+#[test]
+fn derive_encrypt_fields_binds_fields() {
+    let record = Record {
+        id: 7,
+        ssn: "123-45-6789".to_string(),
+        notes: "notes".to_string(),
+        api_token: None,
+    };
+    let key = DerivedKey::new("password", &fast_kdf()).unwrap();
+
+    // Fields of the same type can not be swapped
+    let mut swapped = record.seal(&key).unwrap();
+    std::mem::swap(&mut swapped.ssn, &mut swapped.notes);
+    assert!(matches!(swapped.unseal(&key), Err(DecryptError::Tampered)));
+
+    // Nor can the same field be moved to another row
+    let row_7 = record.seal_with_aad(&key, b"7").unwrap();
+    let mut row_8 = Record { id: 8, ..record }
+        .seal_with_aad(&key, b"8")
+        .unwrap();
+    assert_eq!(
+        row_7.unseal_with_aad(&key, b"7").unwrap().ssn,
+        "123-45-6789"
+    );
+    row_8.ssn = row_7.ssn;
+    assert!(matches!(
+        row_8.unseal_with_aad(&key, b"8"),
+        Err(DecryptError::Tampered)
+    ));
+}